@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS chat.session (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	user_id UUID NOT NULL,
+	refresh_nonce UUID NOT NULL DEFAULT gen_random_uuid(),
+	user_agent TEXT,
+	ip VARCHAR(45),
+	created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	last_used_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	expires_at TIMESTAMP NOT NULL,
+	revoked_at TIMESTAMP,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id)
+);
+
+CREATE INDEX IF NOT EXISTS session_user_id_idx ON chat.session (user_id);
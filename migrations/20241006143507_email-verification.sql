@@ -0,0 +1,4 @@
+ALTER TABLE chat.user
+	ADD COLUMN email_verified_at TIMESTAMP;
+
+UPDATE chat.user SET email_verified_at = NOW()::timestamp;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS chat.totp (
+	user_id UUID PRIMARY KEY,
+	secret VARCHAR(64) NOT NULL,
+	enabled_at TIMESTAMP,
+	last_used_step BIGINT,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id)
+);
+
+CREATE TABLE IF NOT EXISTS chat.recovery_code (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	user_id UUID NOT NULL,
+	code_hash VARCHAR(64) NOT NULL,
+	used_at TIMESTAMP,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id)
+);
+
+CREATE INDEX IF NOT EXISTS recovery_code_user_id_idx ON chat.recovery_code (user_id);
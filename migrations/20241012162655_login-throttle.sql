@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS chat.login_throttle (
+	key VARCHAR(300) PRIMARY KEY,
+	failures INT NOT NULL,
+	last_failure_at TIMESTAMP NOT NULL,
+	blocked_until TIMESTAMP
+);
@@ -0,0 +1,2 @@
+ALTER TABLE chat.user
+	ALTER COLUMN password TYPE VARCHAR(255);
@@ -0,0 +1,6 @@
+ALTER TABLE chat.user_chat
+	ADD COLUMN joined_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp);
+
+-- Messages of the deleted users are kept without an author if they chose so
+ALTER TABLE chat.message
+	ALTER COLUMN user_id DROP NOT NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS chat.data_export (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	user_id UUID NOT NULL,
+	status VARCHAR(10) NOT NULL DEFAULT 'pending',
+	content TEXT,
+	created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	completed_at TIMESTAMP,
+	expires_at TIMESTAMP,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id)
+);
+
+CREATE INDEX IF NOT EXISTS data_export_user_id_idx ON chat.data_export (user_id);
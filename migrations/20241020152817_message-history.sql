@@ -0,0 +1,6 @@
+UPDATE chat.message SET created_at = NOW()::timestamp WHERE created_at IS NULL;
+
+ALTER TABLE chat.message
+	ALTER COLUMN created_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS message_chat_id_created_at_idx ON chat.message (chat_id, created_at, id);
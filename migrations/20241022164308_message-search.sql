@@ -0,0 +1,4 @@
+ALTER TABLE chat.message
+	ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (to_tsvector('english', content)) STORED;
+
+CREATE INDEX IF NOT EXISTS message_search_vector_idx ON chat.message USING GIN (search_vector);
@@ -0,0 +1,2 @@
+ALTER TABLE chat.chat
+	ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp);
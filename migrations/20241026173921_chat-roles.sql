@@ -0,0 +1,13 @@
+CREATE TYPE chat.chat_role AS ENUM ('read_only', 'member', 'moderator', 'admin', 'owner');
+
+ALTER TABLE chat.user_chat
+	ADD COLUMN role chat.chat_role NOT NULL DEFAULT 'member';
+
+INSERT INTO chat.user_chat (user_id, chat_id, role)
+SELECT c.admin_id, c.id, 'owner' FROM chat.chat AS c
+ON CONFLICT (user_id, chat_id) DO UPDATE SET role = 'owner';
+
+ALTER TABLE chat.chat
+	DROP COLUMN admin_id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_chat_owner_idx ON chat.user_chat (chat_id) WHERE role = 'owner';
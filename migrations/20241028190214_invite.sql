@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS chat.invite (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	chat_id UUID NOT NULL,
+	code VARCHAR(20) UNIQUE NOT NULL,
+	created_by UUID,
+	created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	expires_at TIMESTAMP,
+	max_uses INT,
+	uses INT NOT NULL DEFAULT 0,
+	revoked_at TIMESTAMP,
+	FOREIGN KEY(chat_id) REFERENCES chat.chat(id) ON DELETE CASCADE,
+	FOREIGN KEY(created_by) REFERENCES chat.user(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS invite_chat_id_idx ON chat.invite (chat_id);
+
+CREATE TABLE IF NOT EXISTS chat.invite_redemption (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	invite_id UUID NOT NULL,
+	user_id UUID NOT NULL,
+	redeemed_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	FOREIGN KEY(invite_id) REFERENCES chat.invite(id) ON DELETE CASCADE,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id) ON DELETE CASCADE
+);
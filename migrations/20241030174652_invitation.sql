@@ -0,0 +1,18 @@
+ALTER TABLE chat.chat
+	ADD COLUMN invitations_required BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE IF NOT EXISTS chat.invitation (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	chat_id UUID NOT NULL,
+	inviter_id UUID,
+	invitee_id UUID NOT NULL,
+	status VARCHAR(10) NOT NULL DEFAULT 'pending',
+	created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	responded_at TIMESTAMP,
+	FOREIGN KEY(chat_id) REFERENCES chat.chat(id) ON DELETE CASCADE,
+	FOREIGN KEY(inviter_id) REFERENCES chat.user(id) ON DELETE SET NULL,
+	FOREIGN KEY(invitee_id) REFERENCES chat.user(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS invitation_pending_idx ON chat.invitation (chat_id, invitee_id) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS invitation_invitee_id_idx ON chat.invitation (invitee_id);
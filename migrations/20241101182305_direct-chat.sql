@@ -0,0 +1,16 @@
+CREATE TYPE chat.chat_kind AS ENUM ('group', 'direct');
+
+ALTER TABLE chat.chat
+	ADD COLUMN kind chat.chat_kind NOT NULL DEFAULT 'group';
+
+-- Direct chats are unnamed, they are shown by the username of the other party
+CREATE TABLE IF NOT EXISTS chat.direct_chat (
+	chat_id UUID PRIMARY KEY,
+	first_user_id UUID NOT NULL,
+	second_user_id UUID NOT NULL,
+	FOREIGN KEY(chat_id) REFERENCES chat.chat(id) ON DELETE CASCADE,
+	FOREIGN KEY(first_user_id) REFERENCES chat.user(id) ON DELETE CASCADE,
+	FOREIGN KEY(second_user_id) REFERENCES chat.user(id) ON DELETE CASCADE,
+	CHECK (first_user_id < second_user_id),
+	UNIQUE (first_user_id, second_user_id)
+);
@@ -0,0 +1,21 @@
+CREATE TYPE chat.chat_visibility AS ENUM ('private', 'public', 'request_to_join');
+
+ALTER TABLE chat.chat
+	ADD COLUMN visibility chat.chat_visibility NOT NULL DEFAULT 'private';
+
+CREATE INDEX IF NOT EXISTS chat_visibility_idx ON chat.chat (visibility) WHERE visibility <> 'private';
+
+CREATE TABLE IF NOT EXISTS chat.join_request (
+	id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+	chat_id UUID NOT NULL,
+	user_id UUID NOT NULL,
+	status VARCHAR(10) NOT NULL DEFAULT 'pending',
+	created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	responded_at TIMESTAMP,
+	responded_by UUID,
+	FOREIGN KEY(chat_id) REFERENCES chat.chat(id) ON DELETE CASCADE,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id) ON DELETE CASCADE,
+	FOREIGN KEY(responded_by) REFERENCES chat.user(id) ON DELETE SET NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS join_request_pending_idx ON chat.join_request (chat_id, user_id) WHERE status = 'pending';
@@ -0,0 +1,7 @@
+ALTER TABLE chat.message
+	ADD COLUMN reply_to_id UUID,
+	ADD COLUMN thread_root_id UUID,
+	ADD FOREIGN KEY(reply_to_id) REFERENCES chat.message(id) ON DELETE SET NULL,
+	ADD FOREIGN KEY(thread_root_id) REFERENCES chat.message(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS message_thread_root_id_idx ON chat.message (thread_root_id, created_at, id);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS chat.reaction (
+	message_id UUID NOT NULL,
+	user_id UUID NOT NULL,
+	emoji VARCHAR(32) NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT(NOW()::timestamp),
+	PRIMARY KEY(message_id, user_id, emoji),
+	FOREIGN KEY(message_id) REFERENCES chat.message(id) ON DELETE CASCADE,
+	FOREIGN KEY(user_id) REFERENCES chat.user(id) ON DELETE CASCADE
+);
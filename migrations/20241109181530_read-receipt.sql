@@ -0,0 +1,11 @@
+-- The last read message is a position in the history, so it isn't cleared when the message is deleted
+ALTER TABLE chat.user_chat
+	ADD COLUMN last_read_message_id UUID,
+	ADD COLUMN last_read_at TIMESTAMP;
+
+UPDATE chat.user_chat AS uc SET last_read_message_id = last.id, last_read_at = last.created_at
+FROM (
+	SELECT DISTINCT ON (chat_id) chat_id, id, created_at FROM chat.message
+	ORDER BY chat_id, created_at DESC, id DESC
+) AS last
+WHERE last.chat_id = uc.chat_id;
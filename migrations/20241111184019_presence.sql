@@ -0,0 +1,5 @@
+CREATE TYPE chat.presence_status AS ENUM ('available', 'away', 'do_not_disturb');
+
+ALTER TABLE chat.user
+	ADD COLUMN presence_status chat.presence_status NOT NULL DEFAULT 'available',
+	ADD COLUMN last_seen_at TIMESTAMP;
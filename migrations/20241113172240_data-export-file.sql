@@ -0,0 +1,3 @@
+-- Finished exports are written into files in EXPORT_DIR instead
+ALTER TABLE chat.data_export
+	DROP COLUMN IF EXISTS content;
@@ -0,0 +1,10 @@
+CREATE TYPE chat.join_request_status AS ENUM ('pending', 'approved', 'rejected');
+
+DROP INDEX IF EXISTS chat.join_request_pending_idx;
+
+ALTER TABLE chat.join_request
+	ALTER COLUMN status DROP DEFAULT,
+	ALTER COLUMN status TYPE chat.join_request_status USING status::chat.join_request_status,
+	ALTER COLUMN status SET DEFAULT 'pending';
+
+CREATE UNIQUE INDEX IF NOT EXISTS join_request_pending_idx ON chat.join_request (chat_id, user_id) WHERE status = 'pending';
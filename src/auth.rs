@@ -1,28 +1,54 @@
 use std::sync::Arc;
 
-use authentication::{get_me, login};
+use authentication::{get_me, login, refresh};
 use axum::{
     middleware,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
+use password_reset::{forgot_password, reset_password};
 use registration::register;
+use session::{get_sessions, logout, logout_all, revoke_session};
+use two_factor::{disable_two_factor, enable_two_factor, login_mfa, setup_two_factor};
+use verification::{resend_verification, verify_email};
 
 use crate::{middlewares::jwt_authorization, AppState};
 
 pub mod authentication;
+pub mod hasher;
 pub mod jwt;
+pub mod keys;
+pub mod password_reset;
 pub mod registration;
+pub mod secret;
+pub mod session;
+pub mod throttle;
+pub mod two_factor;
+pub mod verification;
 
 pub fn routes(shared_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let authorized = Router::new()
+        .route("/me", get(get_me))
+        .route("/logout", post(logout))
+        .route("/logout-all", post(logout_all))
+        .route("/sessions", get(get_sessions))
+        .route("/sessions/:session_id", delete(revoke_session))
+        .route("/2fa/setup", post(setup_two_factor))
+        .route("/2fa/enable", post(enable_two_factor))
+        .route("/2fa/disable", post(disable_two_factor))
+        .layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            jwt_authorization,
+        ));
+
     Router::new()
         .route("/login", post(login))
+        .route("/login/mfa", post(login_mfa))
         .route("/register", post(register))
-        .route(
-            "/me",
-            get(get_me).layer(middleware::from_fn_with_state(
-                shared_state.clone(),
-                jwt_authorization,
-            )),
-        )
+        .route("/refresh", post(refresh))
+        .route("/verify-email", post(verify_email))
+        .route("/resend-verification", post(resend_verification))
+        .route("/forgot-password", post(forgot_password))
+        .route("/reset-password", post(reset_password))
+        .merge(authorized)
 }
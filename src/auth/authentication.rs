@@ -1,17 +1,29 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::State,
-    http::{header::SET_COOKIE, StatusCode},
+    extract::{ConnectInfo, State},
+    http::{
+        header::{COOKIE, SET_COOKIE, USER_AGENT},
+        HeaderMap, StatusCode,
+    },
     response::{AppendHeaders, IntoResponse, Response},
     Extension, Json,
 };
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
 
 use crate::AppState;
 
-use super::{jwt::create_jwt_token, registration::User};
+use super::{
+    hasher::verify_password,
+    jwt::{create_jwt_token, create_mfa_token, decode_jwt_payload, JwtPayload, TokenType},
+    keys::KeyStore,
+    registration::User,
+    session::{create_session, rotate_session, NewSession, SESSION_LIFETIME_DAYS},
+    throttle::{check_throttle, email_key, ip_key, record_failures, reset_account_throttle},
+    two_factor::is_two_factor_enabled,
+};
 
 #[derive(Deserialize)]
 pub struct LoginDto {
@@ -19,15 +31,27 @@ pub struct LoginDto {
     password: String,
 }
 
-pub async fn login(State(state): State<Arc<AppState>>, Json(payload): Json<LoginDto>) -> Response {
+pub async fn login(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginDto>,
+) -> Response {
+    let throttle_keys = [email_key(&payload.email), ip_key(address.ip())];
+    if let Some(response) = check_throttle(state.login_throttle.as_ref(), &throttle_keys).await {
+        return response;
+    }
+
     struct UserPayload {
         id: Uuid,
         username: String,
         password: String,
+        token_version: i32,
+        email_verified_at: Option<NaiveDateTime>,
     }
     let query_result = sqlx::query_as!(
         UserPayload,
-        "SELECT id, password, username FROM chat.user WHERE email=$1 LIMIT 1",
+        "SELECT id, password, username, token_version, email_verified_at FROM chat.user WHERE email=$1 LIMIT 1",
         &payload.email,
     )
     .fetch_one(&state.db_pool)
@@ -37,21 +61,23 @@ pub async fn login(State(state): State<Arc<AppState>>, Json(payload): Json<Login
         Ok(res) => res,
         Err(e) => match e {
             sqlx::Error::RowNotFound => {
+                record_failures(state.login_throttle.as_ref(), &throttle_keys).await;
                 return (
                     StatusCode::UNAUTHORIZED,
                     "User with such email or password doesn't exist",
                 )
-                    .into_response()
+                    .into_response();
             }
             _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         },
     };
 
-    let is_valid_password = bcrypt::verify(payload.password, &user.password);
+    let is_valid_password = verify_password(&payload.password, &user.password);
 
     match is_valid_password {
         Ok(is_valid) if is_valid => {}
         Ok(_) => {
+            record_failures(state.login_throttle.as_ref(), &throttle_keys).await;
             return (
                 StatusCode::UNAUTHORIZED,
                 "User with such email or password doesn't exist",
@@ -61,9 +87,115 @@ pub async fn login(State(state): State<Arc<AppState>>, Json(payload): Json<Login
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 
-    let access_token = create_jwt_token(
+    // The password is known only now, so this is the only chance to upgrade its hash
+    if state.password_hasher.needs_rehash(&user.password) {
+        if let Ok(password) = state.password_hasher.hash(&payload.password) {
+            sqlx::query!(
+                "UPDATE chat.user SET password = $1 WHERE id = $2 AND password = $3",
+                password,
+                user.id,
+                user.password
+            )
+            .execute(&state.db_pool)
+            .await
+            .ok();
+        }
+    }
+
+    if user.email_verified_at.is_none() {
+        return (
+            StatusCode::FORBIDDEN,
+            "Please, verify your email before logging in",
+        )
+            .into_response();
+    }
+
+    match is_two_factor_enabled(&state.db_pool, user.id).await {
+        Ok(enabled) if !enabled => {}
+        Ok(_) => {
+            let mfa_token = create_mfa_token(
+                &state.keys,
+                user.id,
+                user.token_version,
+                jwt_simple::prelude::Duration::from_mins(5),
+            );
+            return match mfa_token {
+                Ok(mfa_token) => {
+                    (StatusCode::ACCEPTED, Json(MfaPending { mfa_token })).into_response()
+                }
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    reset_account_throttle(state.login_throttle.as_ref(), &payload.email, user.id).await;
+
+    start_session(
+        &state,
+        address,
+        &headers,
         user.id,
-        user.username.clone(),
+        user.username,
+        user.token_version,
+    )
+    .await
+}
+
+/// Returned from `login` instead of the access token when two-factor authentication is enabled.
+/// The token has to be sent together with the code to `/auth/login/mfa`.
+#[derive(Serialize)]
+pub struct MfaPending {
+    mfa_token: String,
+}
+
+/// Creates a new session for the user who has just proven their identity, and issues its tokens.
+pub async fn start_session(
+    state: &AppState,
+    address: SocketAddr,
+    headers: &HeaderMap,
+    user_id: Uuid,
+    username: String,
+    token_version: i32,
+) -> Response {
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok());
+    let session = create_session(
+        &state.db_pool,
+        user_id,
+        user_agent,
+        Some(address.ip().to_string()),
+    )
+    .await;
+
+    match session {
+        Ok(session) => issue_tokens(&state.keys, user_id, username, token_version, session),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub const REFRESH_COOKIE: &str = "Chat-Refresh";
+
+/// Creates a new pair of access and refresh tokens for the user.
+/// The access token is returned in the body, and the refresh token is set as a cookie.
+fn issue_tokens(
+    keys: &KeyStore,
+    user_id: Uuid,
+    username: String,
+    token_version: i32,
+    session: NewSession,
+) -> Response {
+    let access_token = create_jwt_token(
+        keys,
+        JwtPayload {
+            id: user_id,
+            username: username.clone(),
+            token_type: TokenType::Access,
+            token_version,
+            session_id: session.id,
+            nonce: None,
+        },
         jwt_simple::prelude::Duration::from_hours(2),
     );
 
@@ -75,9 +207,16 @@ pub async fn login(State(state): State<Arc<AppState>>, Json(payload): Json<Login
     };
 
     let refresh_token = create_jwt_token(
-        user.id,
-        user.username,
-        jwt_simple::prelude::Duration::from_days(3),
+        keys,
+        JwtPayload {
+            id: user_id,
+            username,
+            token_type: TokenType::Refresh,
+            token_version,
+            session_id: session.id,
+            nonce: Some(session.refresh_nonce),
+        },
+        jwt_simple::prelude::Duration::from_days(SESSION_LIFETIME_DAYS as u64),
     );
     let refresh_token = match refresh_token {
         Ok(token) => token,
@@ -86,11 +225,92 @@ pub async fn login(State(state): State<Arc<AppState>>, Json(payload): Json<Login
         }
     };
 
-    let headers = AppendHeaders([(SET_COOKIE, format!("Chat-Refresh={refresh_token}"))]);
+    let headers = AppendHeaders([(
+        SET_COOKIE,
+        format!(
+            "{REFRESH_COOKIE}={refresh_token}; Max-Age={}; HttpOnly; SameSite=Strict",
+            60 * 60 * 24 * SESSION_LIFETIME_DAYS
+        ),
+    )]);
 
     (StatusCode::OK, headers, access_token).into_response()
 }
 
+/// Finds the value of the cookie with the given name in the `Cookie` header.
+fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+pub async fn refresh(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let refresh_token = match get_cookie(&headers, REFRESH_COOKIE) {
+        Some(token) => token,
+        None => return (StatusCode::UNAUTHORIZED, "Missing refresh token").into_response(),
+    };
+
+    let jwt_payload = match decode_jwt_payload(&state.keys, refresh_token, TokenType::Refresh) {
+        Ok(payload) => payload,
+        Err(_) => return (StatusCode::UNAUTHORIZED, "Invalid refresh token").into_response(),
+    };
+
+    struct UserPayload {
+        id: Uuid,
+        username: String,
+        token_version: i32,
+    }
+    let query_result = sqlx::query_as!(
+        UserPayload,
+        "SELECT id, username, token_version FROM chat.user WHERE id=$1 LIMIT 1",
+        jwt_payload.id,
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let user = match query_result {
+        Ok(res) => res,
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                return (StatusCode::UNAUTHORIZED, "User of this token doesn't exist")
+                    .into_response()
+            }
+            _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    };
+
+    if user.token_version != jwt_payload.token_version {
+        return (StatusCode::UNAUTHORIZED, "This session has ended").into_response();
+    }
+
+    let nonce = match jwt_payload.nonce {
+        Some(nonce) => nonce,
+        None => return (StatusCode::UNAUTHORIZED, "Invalid refresh token").into_response(),
+    };
+
+    let session = rotate_session(&state.db_pool, jwt_payload.session_id, user.id, nonce).await;
+
+    match session {
+        Ok(session) => issue_tokens(
+            &state.keys,
+            user.id,
+            user.username,
+            user.token_version,
+            session,
+        ),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                (StatusCode::UNAUTHORIZED, "This session has ended").into_response()
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    }
+}
+
 #[derive(Serialize)]
 pub struct NormalizedUser {
     pub id: Uuid,
@@ -0,0 +1,46 @@
+use self::{argon2id::Argon2idHasher, bcrypt::BcryptHasher};
+
+pub mod argon2id;
+pub mod bcrypt;
+
+/// Hashes the passwords with the configured algorithm and cost.
+pub trait PasswordHasher: Send + Sync {
+    fn hash(&self, password: &str) -> Result<String, String>;
+    /// Whether the hash was made with another algorithm or cost,
+    /// so it should be replaced the next time the password is known.
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+/// Checks the password against a hash made by any of the supported algorithms,
+/// so that the hashes made before the algorithm or cost change keep working.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
+    if hash.starts_with("$argon2") {
+        argon2id::verify(password, hash)
+    } else {
+        self::bcrypt::verify(password, hash)
+    }
+}
+
+fn var_or(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Creates the bcrypt hasher if `PASSWORD_HASHER` is `bcrypt`, and the Argon2id one otherwise.
+/// The costs are read from `BCRYPT_COST`, or `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS`
+/// and `ARGON2_PARALLELISM` respectively.
+pub fn init_password_hasher() -> Box<dyn PasswordHasher> {
+    match std::env::var("PASSWORD_HASHER").as_deref() {
+        Ok("bcrypt") => Box::new(BcryptHasher::new(var_or("BCRYPT_COST", 10))),
+        _ => Box::new(
+            Argon2idHasher::new(
+                var_or("ARGON2_MEMORY_KIB", 19 * 1024),
+                var_or("ARGON2_ITERATIONS", 2),
+                var_or("ARGON2_PARALLELISM", 1),
+            )
+            .expect("Invalid Argon2 parameters"),
+        ),
+    }
+}
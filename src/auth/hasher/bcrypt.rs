@@ -0,0 +1,30 @@
+use super::PasswordHasher;
+
+/// Hasher of the passwords created before Argon2id became the default.
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl BcryptHasher {
+    pub fn new(cost: u32) -> Self {
+        BcryptHasher { cost }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        bcrypt::hash(password.as_bytes(), self.cost).map_err(|e| e.to_string())
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        // $2b$<cost>$<salt and hash>
+        match hash.split('$').nth(2).map(|cost| cost.parse::<u32>()) {
+            Some(Ok(cost)) => !hash.starts_with("$2") || cost != self.cost,
+            _ => true,
+        }
+    }
+}
+
+pub fn verify(password: &str, hash: &str) -> Result<bool, String> {
+    bcrypt::verify(password, hash).map_err(|e| e.to_string())
+}
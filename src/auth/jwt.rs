@@ -1,38 +1,185 @@
 use jwt_simple::{
-    claims::Claims,
-    prelude::{HS256Key, MACLike},
+    claims::{Claims, JWTClaims},
+    JWTError,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sqlx::types::Uuid;
 
+use super::keys::KeyStore;
+
+/// Distinguishes what a token may be used for, so that a long-lived refresh
+/// token cannot be presented as an access token and vice versa.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Access,
+    Refresh,
+    EmailVerification,
+    MfaPending,
+}
+
+/// Claims which every kind of token has, so that their type can be checked.
+trait TypedClaims {
+    fn token_type(&self) -> TokenType;
+}
+
+fn verify<T: Serialize + DeserializeOwned + TypedClaims>(
+    keys: &KeyStore,
+    jwt_token: &str,
+    expected_type: TokenType,
+) -> Result<JWTClaims<T>, jwt_simple::Error> {
+    let claims = keys.verify::<T>(jwt_token)?;
+
+    if claims.custom.token_type() != expected_type {
+        return Err(JWTError::InternalError("Unexpected token type".to_string()).into());
+    }
+
+    Ok(claims)
+}
+
 #[derive(Serialize, Deserialize)]
+struct CustomClaims {
+    id: Uuid,
+    username: String,
+    token_type: TokenType,
+    token_version: i32,
+}
+
+impl TypedClaims for CustomClaims {
+    fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+}
+
 pub struct JwtPayload {
     pub id: Uuid,
     pub username: String,
+    pub token_type: TokenType,
+    /// Has to match `chat.user.token_version`, which is bumped to invalidate
+    /// all the tokens issued before, e.g. after the password change.
+    pub token_version: i32,
+    /// Id of the session in `chat.session`, stored in the `jti` claim.
+    pub session_id: Uuid,
+    /// Set only for refresh tokens. Changes every time the refresh token is rotated,
+    /// so that an already used refresh token cannot be exchanged again.
+    pub nonce: Option<Uuid>,
 }
 
 pub fn create_jwt_token(
-    id: Uuid,
-    username: String,
+    keys: &KeyStore,
+    payload: JwtPayload,
     duration: jwt_simple::prelude::Duration,
 ) -> Result<String, jwt_simple::Error> {
-    let key = HS256Key::from_bytes(
-        (std::env::var("JWT_SECRET").expect("JWT_SECRET have to be defined")).as_bytes(),
-    );
-    let claims = Claims::with_custom_claims(JwtPayload { username, id }, duration);
+    let mut claims = Claims::with_custom_claims(
+        CustomClaims {
+            username: payload.username,
+            id: payload.id,
+            token_type: payload.token_type,
+            token_version: payload.token_version,
+        },
+        duration,
+    )
+    .with_jwt_id(payload.session_id);
 
-    key.authenticate(claims)
+    if let Some(nonce) = payload.nonce {
+        claims = claims.with_nonce(nonce);
+    }
+
+    keys.sign(claims)
 }
 
-pub fn decode_jwt_payload(jwt_token: &str) -> Result<JwtPayload, jwt_simple::Error> {
-    let key = HS256Key::from_bytes(
-        (std::env::var("JWT_SECRET").expect("JWT_SECRET have to be defined")).as_bytes(),
-    );
+/// Verifies the token and returns its payload.
+/// Fails if the token is invalid, expired, or is not of the `expected_type`.
+pub fn decode_jwt_payload(
+    keys: &KeyStore,
+    jwt_token: &str,
+    expected_type: TokenType,
+) -> Result<JwtPayload, jwt_simple::Error> {
+    let claims = verify::<CustomClaims>(keys, jwt_token, expected_type)?;
 
-    let claims = key.verify_token::<JwtPayload>(jwt_token, None)?;
+    let session_id = claims
+        .jwt_id
+        .and_then(|jti| Uuid::parse_str(&jti).ok())
+        .ok_or(JWTError::InternalError("Missing session id".to_string()))?;
+    let nonce = match claims.nonce {
+        Some(nonce) => Some(Uuid::parse_str(&nonce)?),
+        None => None,
+    };
 
     Ok(JwtPayload {
         username: claims.custom.username,
         id: claims.custom.id,
+        token_type: claims.custom.token_type,
+        token_version: claims.custom.token_version,
+        session_id,
+        nonce,
     })
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct EmailPayload {
+    pub id: Uuid,
+    /// Email to be verified. Differs from the current email of the user when they change it.
+    pub email: String,
+    pub token_version: i32,
+    pub token_type: TokenType,
+}
+
+impl TypedClaims for EmailPayload {
+    fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+}
+
+pub fn create_email_token(
+    keys: &KeyStore,
+    payload: EmailPayload,
+    duration: jwt_simple::prelude::Duration,
+) -> Result<String, jwt_simple::Error> {
+    keys.sign(Claims::with_custom_claims(payload, duration))
+}
+
+/// Verifies the token sent to the email of the user.
+/// Fails if the token is invalid, expired, or is not of the `expected_type`.
+pub fn decode_email_token(
+    keys: &KeyStore,
+    jwt_token: &str,
+    expected_type: TokenType,
+) -> Result<EmailPayload, jwt_simple::Error> {
+    Ok(verify::<EmailPayload>(keys, jwt_token, expected_type)?.custom)
+}
+
+/// Payload of the token which proves that the user entered the correct password,
+/// but still has to enter their second factor to log in.
+#[derive(Serialize, Deserialize)]
+pub struct MfaPayload {
+    pub id: Uuid,
+    pub token_version: i32,
+    pub token_type: TokenType,
+}
+
+impl TypedClaims for MfaPayload {
+    fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+}
+
+pub fn create_mfa_token(
+    keys: &KeyStore,
+    id: Uuid,
+    token_version: i32,
+    duration: jwt_simple::prelude::Duration,
+) -> Result<String, jwt_simple::Error> {
+    keys.sign(Claims::with_custom_claims(
+        MfaPayload {
+            id,
+            token_version,
+            token_type: TokenType::MfaPending,
+        },
+        duration,
+    ))
+}
+
+pub fn decode_mfa_token(keys: &KeyStore, jwt_token: &str) -> Result<MfaPayload, jwt_simple::Error> {
+    Ok(verify::<MfaPayload>(keys, jwt_token, TokenType::MfaPending)?.custom)
+}
@@ -0,0 +1,212 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_simple::{
+    claims::JWTClaims,
+    prelude::{
+        Base64UrlSafeNoPadding, ECDSAP256KeyPairLike, ECDSAP256PublicKeyLike, ES256KeyPair,
+        Ed25519KeyPair, EdDSAKeyPairLike, EdDSAPublicKeyLike, HS256Key, MACLike,
+    },
+    reexports::ct_codecs::Encoder,
+    token::Token,
+    JWTError,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::AppState;
+
+/// Kid of the key made out of `JWT_SECRET` when no keys are configured.
+const DEFAULT_KID: &str = "default";
+
+enum SigningKey {
+    Hs256(HS256Key),
+    Ed25519(Ed25519KeyPair),
+    Es256(ES256KeyPair),
+}
+
+impl SigningKey {
+    fn from_env(kid: &str) -> Self {
+        let prefix = format!(
+            "JWT_KEY_{}",
+            kid.to_uppercase()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        );
+        let alg = std::env::var(format!("{prefix}_ALG")).unwrap_or("HS256".to_string());
+
+        if alg == "HS256" {
+            let secret = std::env::var(format!("{prefix}_SECRET"))
+                .unwrap_or_else(|_| panic!("{prefix}_SECRET have to be defined"));
+            return SigningKey::Hs256(HS256Key::from_bytes(secret.as_bytes()).with_key_id(kid));
+        }
+
+        let pem_path = std::env::var(format!("{prefix}_PEM"))
+            .unwrap_or_else(|_| panic!("{prefix}_PEM have to be defined"));
+        let pem = std::fs::read_to_string(&pem_path)
+            .unwrap_or_else(|_| panic!("Could not read the key of {kid} from {pem_path}"));
+
+        match alg.as_str() {
+            "EdDSA" => SigningKey::Ed25519(
+                Ed25519KeyPair::from_pem(&pem)
+                    .unwrap_or_else(|_| panic!("Invalid EdDSA key of {kid}"))
+                    .with_key_id(kid),
+            ),
+            "ES256" => SigningKey::Es256(
+                ES256KeyPair::from_pem(&pem)
+                    .unwrap_or_else(|_| panic!("Invalid ES256 key of {kid}"))
+                    .with_key_id(kid),
+            ),
+            _ => panic!("Unsupported algorithm {alg} of {kid}"),
+        }
+    }
+}
+
+/// Keys which sign and verify the tokens.
+/// Only the active key signs new tokens, the rest are kept to verify the tokens
+/// issued before the rotation until they expire.
+pub struct KeyStore {
+    active_kid: String,
+    keys: HashMap<String, SigningKey>,
+}
+
+impl KeyStore {
+    /// Loads the keys listed in `JWT_KEYS`, the first of which is the active one.
+    /// Each key is configured with `JWT_KEY_<KID>_ALG` (`HS256`, `EdDSA` or `ES256`)
+    /// and either `JWT_KEY_<KID>_SECRET` or `JWT_KEY_<KID>_PEM`, the path to its private key.
+    /// Falls back to a single HS256 key from `JWT_SECRET`.
+    pub fn from_env() -> Self {
+        let kids = match std::env::var("JWT_KEYS") {
+            Ok(kids) => kids
+                .split(',')
+                .map(|kid| kid.trim().to_string())
+                .filter(|kid| !kid.is_empty())
+                .collect::<Vec<String>>(),
+            Err(_) => {
+                let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET have to be defined");
+                let key = HS256Key::from_bytes(secret.as_bytes()).with_key_id(DEFAULT_KID);
+                return KeyStore {
+                    active_kid: DEFAULT_KID.to_string(),
+                    keys: HashMap::from([(DEFAULT_KID.to_string(), SigningKey::Hs256(key))]),
+                };
+            }
+        };
+
+        let active_kid = kids
+            .first()
+            .expect("JWT_KEYS have to contain at least one key")
+            .clone();
+        let keys = kids
+            .into_iter()
+            .map(|kid| {
+                let key = SigningKey::from_env(&kid);
+                (kid, key)
+            })
+            .collect();
+
+        KeyStore { active_kid, keys }
+    }
+
+    pub fn sign<T: Serialize + DeserializeOwned>(
+        &self,
+        claims: JWTClaims<T>,
+    ) -> Result<String, jwt_simple::Error> {
+        match &self.keys[&self.active_kid] {
+            SigningKey::Hs256(key) => key.authenticate(claims),
+            SigningKey::Ed25519(key) => key.sign(claims),
+            SigningKey::Es256(key) => key.sign(claims),
+        }
+    }
+
+    /// Verifies the token with the key from its `kid` header.
+    /// Tokens without the header were issued before the rotation, so the active key is used for them.
+    pub fn verify<T: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<JWTClaims<T>, jwt_simple::Error> {
+        let metadata = Token::decode_metadata(token)?;
+        let kid = metadata.key_id().unwrap_or(&self.active_kid);
+        let key = self
+            .keys
+            .get(kid)
+            .ok_or(JWTError::InternalError("Unknown signing key".to_string()))?;
+
+        match key {
+            SigningKey::Hs256(key) => key.verify_token::<T>(token, None),
+            SigningKey::Ed25519(key) => key.public_key().verify_token::<T>(token, None),
+            SigningKey::Es256(key) => key.public_key().verify_token::<T>(token, None),
+        }
+    }
+
+    /// Public halves of the asymmetric keys.
+    /// HS256 keys are secret, so they are never exposed.
+    fn jwks(&self) -> Vec<Jwk> {
+        let mut jwks: Vec<Jwk> = self
+            .keys
+            .iter()
+            .filter_map(|(kid, key)| match key {
+                SigningKey::Hs256(_) => None,
+                SigningKey::Ed25519(key) => Some(Jwk {
+                    kty: "OKP",
+                    crv: "Ed25519",
+                    alg: "EdDSA",
+                    key_use: "sig",
+                    kid: kid.clone(),
+                    x: encode(&key.public_key().to_bytes()),
+                    y: None,
+                }),
+                SigningKey::Es256(key) => {
+                    // 0x04 followed by both coordinates
+                    let point = key.public_key().public_key().to_bytes_uncompressed();
+                    Some(Jwk {
+                        kty: "EC",
+                        crv: "P-256",
+                        alg: "ES256",
+                        key_use: "sig",
+                        kid: kid.clone(),
+                        x: encode(&point[1..33]),
+                        y: Some(encode(&point[33..])),
+                    })
+                }
+            })
+            .collect();
+        jwks.sort_by(|a, b| a.kid.cmp(&b.kid));
+        jwks
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    Base64UrlSafeNoPadding::encode_to_string(bytes).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct Jwk {
+    kty: &'static str,
+    crv: &'static str,
+    alg: &'static str,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+    kid: String,
+    x: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// Lets other services verify the tokens signed with the asymmetric keys.
+pub async fn get_jwks(State(state): State<Arc<AppState>>) -> Response {
+    (
+        StatusCode::OK,
+        Json(JwkSet {
+            keys: state.keys.jwks(),
+        }),
+    )
+        .into_response()
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use socketioxide::SocketIo;
+use sqlx::types::Uuid;
+
+use crate::{
+    mailer::{send_in_background, Mail},
+    sockets::disconnect_user,
+    AppState,
+};
+
+use super::{
+    registration::Validity,
+    secret::{generate_secret, hash_secret},
+    session::invalidate_user_tokens,
+    throttle::reset_account_throttle,
+};
+
+/// For how long the reset token can be used after it was sent.
+const RESET_TOKEN_LIFETIME_MINUTES: i32 = 30;
+
+#[derive(Deserialize)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+async fn send_reset_email(state: &AppState, user_id: Uuid, email: String) -> Result<(), String> {
+    let token = generate_secret();
+
+    let mut tx = state.db_pool.begin().await.map_err(|e| e.to_string())?;
+
+    // Only the latest token of the user can be used
+    sqlx::query!(
+        "UPDATE chat.password_reset SET used_at = NOW()::timestamp WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    sqlx::query!(
+        "
+        INSERT INTO chat.password_reset (user_id, token_hash, expires_at)
+        VALUES ($1, $2, NOW()::timestamp + make_interval(mins => $3))
+        ",
+        user_id,
+        hash_secret(&token),
+        RESET_TOKEN_LIFETIME_MINUTES
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "To reset your password, use the following token. It is valid for {RESET_TOKEN_LIFETIME_MINUTES} minutes.\n\n{token}\n\nIf you didn't request the reset, you can ignore this email."
+            ),
+        })
+        .await
+}
+
+/// Sends the password reset token to the email.
+/// Always responds the same way, so that it cannot be used to check which emails are registered.
+pub async fn forgot_password(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ForgotPassword>,
+) -> Response {
+    struct UserPayload {
+        id: Uuid,
+        email: String,
+    }
+    let query_result = sqlx::query_as!(
+        UserPayload,
+        "SELECT id, email FROM chat.user WHERE email = $1 LIMIT 1",
+        payload.email
+    )
+    .fetch_optional(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(Some(user)) => {
+            send_in_background(async move { send_reset_email(&state, user.id, user.email).await });
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(None) => StatusCode::ACCEPTED.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResetPassword {
+    token: String,
+    new_password: String,
+}
+
+/// Sets the new password using the token from `forgot_password`.
+/// The token can be used only once, and all the sessions of the user are ended.
+pub async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    Extension(io): Extension<SocketIo>,
+    Json(payload): Json<ResetPassword>,
+) -> Response {
+    if let Err(message) = payload.new_password.is_valid_password() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let password = match state.password_hasher.hash(&payload.new_password) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not reset the password due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not reset the password due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    let consume_result = sqlx::query!(
+        "
+        UPDATE chat.password_reset SET used_at = NOW()::timestamp
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()::timestamp
+        RETURNING user_id
+        ",
+        hash_secret(&payload.token)
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let user_id = match consume_result {
+        Ok(reset) => reset.user_id,
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                return (StatusCode::BAD_REQUEST, "Invalid or expired reset token").into_response()
+            }
+            _ => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not reset the password due to internal reasons",
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    // Receiving the token proves the ownership of the email as well
+    let update_result = sqlx::query!(
+        "
+        UPDATE chat.user
+        SET password = $1, email_verified_at = COALESCE(email_verified_at, NOW()::timestamp)
+        WHERE id = $2
+        RETURNING email
+        ",
+        password,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let email = match update_result {
+        Ok(user) => user.email,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not update the password",
+            )
+                .into_response();
+        }
+    };
+
+    if invalidate_user_tokens(&mut tx, user_id).await.is_err() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not end your sessions",
+        )
+            .into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            disconnect_user(&io, user_id);
+            // The owner of the email has proven themselves, so the failed logins don't count anymore
+            reset_account_throttle(state.login_throttle.as_ref(), &email, user_id).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not reset the password due to internal reasons",
+        )
+            .into_response(),
+    }
+}
@@ -6,10 +6,13 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
 
-use crate::AppState;
+use crate::{mailer::send_in_background, AppState};
+
+use super::verification::send_verification_email;
 
 #[derive(Serialize, Clone, Debug)]
 pub struct User {
@@ -17,6 +20,8 @@ pub struct User {
     pub username: String,
     pub password: String,
     pub email: String,
+    pub token_version: i32,
+    pub email_verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize)]
@@ -118,6 +123,8 @@ impl Validity for String {
     }
 }
 
+/// Creates the user and sends the verification token to their email.
+/// The user cannot log in until the email is verified.
 pub async fn register(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<RegisterUser>,
@@ -136,34 +143,50 @@ pub async fn register(
         Ok(_) => {}
         Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
     }
-    let pass_encrypt_res = bcrypt::hash(payload.password.as_bytes(), 10);
+    let pass_encrypt_res = state.password_hasher.hash(&payload.password);
 
     let password = match pass_encrypt_res {
         Ok(hash) => hash,
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
 
-    let result =
-        sqlx::query("INSERT INTO chat.user (username, email, password) VALUES ($1, $2, $3)")
-            .bind(payload.username)
-            .bind(payload.email)
-            .bind(password)
-            .execute(&state.db_pool)
-            .await;
-
-    match result {
-        Ok(_) => StatusCode::CREATED.into_response(),
+    struct NewUser {
+        id: Uuid,
+        email: String,
+        token_version: i32,
+    }
+    let result = sqlx::query_as!(
+        NewUser,
+        "INSERT INTO chat.user (username, email, password) VALUES ($1, $2, $3) RETURNING id, email, token_version",
+        payload.username,
+        payload.email,
+        password
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let user = match result {
+        Ok(user) => user,
         Err(e) => match e {
-            sqlx::Error::Database(err) if err.is_unique_violation() => (
-                StatusCode::CONFLICT,
-                "User with such nickname or email already exists",
-            )
-                .into_response(),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Could not register you due to internal reasons",
-            )
-                .into_response(),
+            sqlx::Error::Database(err) if err.is_unique_violation() => {
+                return (
+                    StatusCode::CONFLICT,
+                    "User with such nickname or email already exists",
+                )
+                    .into_response()
+            }
+            _ => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not register you due to internal reasons",
+                )
+                    .into_response()
+            }
         },
-    }
+    };
+
+    send_in_background(async move {
+        send_verification_email(&state, user.id, user.email, user.token_version).await
+    });
+    StatusCode::CREATED.into_response()
 }
@@ -0,0 +1,15 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Generates a random hex-encoded secret, e.g. for one-time tokens sent by email.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes the secret before storing it in the database,
+/// so that whoever can read the table still cannot use the secrets.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
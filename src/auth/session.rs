@@ -0,0 +1,277 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::{header::SET_COOKIE, HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{types::Uuid, PgConnection, Pool, Postgres};
+
+use crate::AppState;
+
+use super::{
+    authentication::REFRESH_COOKIE,
+    jwt::{decode_jwt_payload, TokenType},
+    registration::User,
+};
+
+/// For how long a session stays alive without its refresh token being exchanged.
+pub const SESSION_LIFETIME_DAYS: i32 = 3;
+
+/// Session of the request, inserted by `jwt_authorization` next to the `User`.
+#[derive(Clone, Debug)]
+pub struct CurrentSession {
+    pub id: Uuid,
+}
+
+pub struct NewSession {
+    pub id: Uuid,
+    pub refresh_nonce: Uuid,
+}
+
+pub async fn create_session(
+    executor: &Pool<Postgres>,
+    user_id: Uuid,
+    user_agent: Option<&str>,
+    ip: Option<String>,
+) -> sqlx::Result<NewSession> {
+    sqlx::query_as!(
+        NewSession,
+        "
+        INSERT INTO chat.session (user_id, user_agent, ip, expires_at)
+        VALUES ($1, $2, $3, NOW()::timestamp + make_interval(days => $4))
+        RETURNING id, refresh_nonce
+        ",
+        user_id,
+        user_agent,
+        ip,
+        SESSION_LIFETIME_DAYS,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Replaces the refresh nonce of the session and prolongs its life.
+/// If the nonce doesn't match, the refresh token was either forged or already used,
+/// so the whole session is revoked to lock out whoever holds the stolen token.
+pub async fn rotate_session(
+    executor: &Pool<Postgres>,
+    session_id: Uuid,
+    user_id: Uuid,
+    refresh_nonce: Uuid,
+) -> sqlx::Result<NewSession> {
+    let rotation_result = sqlx::query_as!(
+        NewSession,
+        "
+        UPDATE chat.session
+        SET refresh_nonce = gen_random_uuid(),
+            last_used_at = NOW()::timestamp,
+            expires_at = NOW()::timestamp + make_interval(days => $4)
+        WHERE id = $1 AND user_id = $2 AND refresh_nonce = $3
+            AND revoked_at IS NULL AND expires_at > NOW()::timestamp
+        RETURNING id, refresh_nonce
+        ",
+        session_id,
+        user_id,
+        refresh_nonce,
+        SESSION_LIFETIME_DAYS,
+    )
+    .fetch_one(executor)
+    .await;
+
+    if let Err(sqlx::Error::RowNotFound) = rotation_result {
+        sqlx::query!(
+            "UPDATE chat.session SET revoked_at = NOW()::timestamp WHERE id = $1 AND revoked_at IS NULL",
+            session_id
+        )
+        .execute(executor)
+        .await?;
+    }
+
+    rotation_result
+}
+
+/// Finds the owner of the access token.
+/// Returns `None` if the token is invalid or its session was revoked or has expired.
+pub async fn find_session_user(
+    state: &AppState,
+    jwt_token: &str,
+) -> Option<(User, CurrentSession)> {
+    let jwt_payload = decode_jwt_payload(&state.keys, jwt_token, TokenType::Access).ok()?;
+
+    let user = sqlx::query_as!(
+        User,
+        "
+        SELECT u.id, u.username, u.password, u.email, u.token_version, u.email_verified_at
+        FROM chat.user AS u
+        INNER JOIN chat.session AS s
+        ON s.user_id = u.id
+        WHERE s.id = $1 AND u.id = $2 AND u.token_version = $3
+            AND s.revoked_at IS NULL AND s.expires_at > NOW()::timestamp
+        LIMIT 1
+        ",
+        jwt_payload.session_id,
+        jwt_payload.id,
+        jwt_payload.token_version,
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .ok()?;
+
+    Some((
+        user,
+        CurrentSession {
+            id: jwt_payload.session_id,
+        },
+    ))
+}
+
+/// Invalidates every token issued to the user so far by bumping their token version,
+/// and revokes all of their sessions.
+/// Live sockets of the user have to be disconnected separately with `disconnect_user`.
+pub async fn invalidate_user_tokens(
+    executor: &mut PgConnection,
+    user_id: Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE chat.user SET token_version = token_version + 1 WHERE id = $1",
+        user_id
+    )
+    .execute(&mut *executor)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE chat.session SET revoked_at = NOW()::timestamp WHERE user_id = $1 AND revoked_at IS NULL",
+        user_id
+    )
+    .execute(&mut *executor)
+    .await?;
+
+    Ok(())
+}
+
+fn clear_refresh_cookie() -> AppendHeaders<[(HeaderName, String); 1]> {
+    AppendHeaders([(
+        SET_COOKIE,
+        format!("{REFRESH_COOKIE}=; Max-Age=0; HttpOnly; SameSite=Strict"),
+    )])
+}
+
+pub async fn logout(
+    Extension(session): Extension<CurrentSession>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let revoke_result = sqlx::query!(
+        "UPDATE chat.session SET revoked_at = NOW()::timestamp WHERE id = $1 AND revoked_at IS NULL",
+        session.id
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match revoke_result {
+        Ok(_) => (StatusCode::NO_CONTENT, clear_refresh_cookie()).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not log you out due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+pub async fn logout_all(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let revoke_result = sqlx::query!(
+        "UPDATE chat.session SET revoked_at = NOW()::timestamp WHERE user_id = $1 AND revoked_at IS NULL",
+        user.id
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match revoke_result {
+        Ok(_) => (StatusCode::NO_CONTENT, clear_refresh_cookie()).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not log you out of your sessions due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct NormalizedSession {
+    id: Uuid,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: NaiveDateTime,
+    last_used_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+    current: bool,
+}
+
+pub async fn get_sessions(
+    Extension(user): Extension<User>,
+    Extension(session): Extension<CurrentSession>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let query_result = sqlx::query_as!(
+        NormalizedSession,
+        r#"
+        SELECT id, user_agent, ip, created_at, last_used_at, expires_at, id = $2 AS "current!"
+        FROM chat.session
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()::timestamp
+        ORDER BY last_used_at DESC
+        "#,
+        user.id,
+        session.id
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(sessions) => (StatusCode::OK, Json(sessions)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not find your sessions due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+pub async fn revoke_session(
+    Path(session_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let revoke_result = sqlx::query!(
+        "
+        UPDATE chat.session SET revoked_at = NOW()::timestamp
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        RETURNING id
+        ",
+        session_id,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match revoke_result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::NOT_FOUND,
+                "Could not find session with such an id",
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not revoke the session due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
@@ -0,0 +1,141 @@
+use std::{future::Future, net::IpAddr, pin::Pin, time::Duration};
+
+use axum::{
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+};
+use memory::MemoryThrottle;
+use postgres::PostgresThrottle;
+use sqlx::{types::Uuid, Pool, Postgres};
+
+pub mod memory;
+pub mod postgres;
+
+pub type ThrottleResult<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+/// Counts failed attempts per key, e.g. per email or per IP address,
+/// and blocks the key for a while once there are too many of them.
+pub trait LoginThrottle: Send + Sync {
+    /// Returns for how long the key is still blocked, or `None` if it isn't.
+    fn blocked_for<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, Option<Duration>>;
+    fn record_failure<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, ()>;
+    /// Forgets all the failed attempts of the key, unblocking it.
+    fn reset<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, ()>;
+}
+
+#[derive(Clone, Debug)]
+pub struct ThrottleConfig {
+    /// How many failures in a row are allowed without any delay.
+    pub free_attempts: u32,
+    /// Delay after the first failure past the free ones. Doubles with every next failure.
+    pub base_delay: Duration,
+    /// After how many failures the key gets locked for `lock_duration`.
+    pub lock_after: u32,
+    pub lock_duration: Duration,
+    /// Failures are forgotten once there were none for this long.
+    pub forget_after: Duration,
+}
+
+impl ThrottleConfig {
+    /// Reads the config from `LOGIN_FREE_ATTEMPTS`, `LOGIN_BACKOFF_SECONDS`,
+    /// `LOGIN_LOCK_AFTER` and `LOGIN_LOCK_MINUTES`, using the defaults for the missing ones.
+    pub fn from_env() -> Self {
+        fn var_or(name: &str, default: u64) -> u64 {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let lock_duration = Duration::from_secs(var_or("LOGIN_LOCK_MINUTES", 15) * 60);
+        ThrottleConfig {
+            free_attempts: var_or("LOGIN_FREE_ATTEMPTS", 3) as u32,
+            base_delay: Duration::from_secs(var_or("LOGIN_BACKOFF_SECONDS", 1)),
+            lock_after: var_or("LOGIN_LOCK_AFTER", 10) as u32,
+            lock_duration,
+            forget_after: lock_duration,
+        }
+    }
+
+    /// Returns for how long the key has to be blocked after the given number of failures.
+    pub fn delay_after(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.lock_after {
+            return Some(self.lock_duration);
+        }
+        if failures < self.free_attempts {
+            return None;
+        }
+
+        let exponent = (failures - self.free_attempts).min(16);
+        Some(
+            self.base_delay
+                .saturating_mul(2u32.pow(exponent))
+                .min(self.lock_duration),
+        )
+    }
+}
+
+pub fn email_key(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+pub fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+pub fn user_key(user_id: Uuid) -> String {
+    format!("user:{user_id}")
+}
+
+/// Returns `429 Too Many Requests` with the `Retry-After` header if any of the keys is blocked.
+pub async fn check_throttle(throttle: &dyn LoginThrottle, keys: &[String]) -> Option<Response> {
+    let mut retry_after = Duration::ZERO;
+    for key in keys {
+        match throttle.blocked_for(key).await {
+            Ok(Some(blocked_for)) => retry_after = retry_after.max(blocked_for),
+            Ok(None) => {}
+            Err(_) => return Some(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+        }
+    }
+
+    if retry_after.is_zero() {
+        return None;
+    }
+
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Some(
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, seconds.to_string())],
+            "Too many failed attempts. Please, try again later",
+        )
+            .into_response(),
+    )
+}
+
+/// Forgets the failed attempts against the account once the user has proven themselves.
+/// The IP counter is kept on purpose: it counts failures against every account from the address,
+/// so clearing it on one success would let an attacker with any working account reset it between guesses.
+/// It runs out on its own after `forget_after`.
+pub async fn reset_account_throttle(throttle: &dyn LoginThrottle, email: &str, user_id: Uuid) {
+    throttle.reset(&email_key(email)).await.ok();
+    throttle.reset(&user_key(user_id)).await.ok();
+}
+
+pub async fn record_failures(throttle: &dyn LoginThrottle, keys: &[String]) {
+    for key in keys {
+        throttle.record_failure(key).await.ok();
+    }
+}
+
+/// Creates the in-memory throttle if `LOGIN_THROTTLE` is `memory`,
+/// which is suitable only for a single instance of the server.
+/// Otherwise, the counters are stored in the database.
+pub fn init_login_throttle(db_pool: Pool<Postgres>) -> Box<dyn LoginThrottle> {
+    let config = ThrottleConfig::from_env();
+
+    match std::env::var("LOGIN_THROTTLE").as_deref() {
+        Ok("memory") => Box::new(MemoryThrottle::new(config)),
+        _ => Box::new(PostgresThrottle::new(db_pool, config)),
+    }
+}
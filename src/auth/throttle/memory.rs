@@ -0,0 +1,148 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{LoginThrottle, ThrottleConfig, ThrottleResult};
+
+struct Attempts {
+    failures: u32,
+    last_failure_at: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Keeps the counters in the memory of the process.
+pub struct MemoryThrottle {
+    config: ThrottleConfig,
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl MemoryThrottle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        MemoryThrottle {
+            config,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl LoginThrottle for MemoryThrottle {
+    fn blocked_for<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, Option<Duration>> {
+        Box::pin(async move {
+            let attempts = self.attempts.lock().map_err(|e| e.to_string())?;
+            let now = Instant::now();
+
+            Ok(attempts
+                .get(key)
+                .and_then(|attempts| attempts.blocked_until)
+                .filter(|blocked_until| *blocked_until > now)
+                .map(|blocked_until| blocked_until - now))
+        })
+    }
+
+    fn record_failure<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, ()> {
+        Box::pin(async move {
+            let mut attempts = self.attempts.lock().map_err(|e| e.to_string())?;
+            let now = Instant::now();
+            let forget_after = self.config.forget_after;
+
+            attempts.retain(|_, attempts| now - attempts.last_failure_at < forget_after);
+
+            let entry = attempts.entry(key.to_string()).or_insert(Attempts {
+                failures: 0,
+                last_failure_at: now,
+                blocked_until: None,
+            });
+            entry.failures += 1;
+            entry.last_failure_at = now;
+            entry.blocked_until = self
+                .config
+                .delay_after(entry.failures)
+                .map(|delay| now + delay);
+
+            Ok(())
+        })
+    }
+
+    fn reset<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, ()> {
+        Box::pin(async move {
+            let mut attempts = self.attempts.lock().map_err(|e| e.to_string())?;
+            attempts.remove(key);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(forget_after: Duration) -> MemoryThrottle {
+        MemoryThrottle::new(ThrottleConfig {
+            free_attempts: 2,
+            base_delay: Duration::from_secs(1),
+            lock_after: 5,
+            lock_duration: Duration::from_secs(60),
+            forget_after,
+        })
+    }
+
+    async fn fail(throttle: &MemoryThrottle, key: &str, times: u32) {
+        for _ in 0..times {
+            throttle.record_failure(key).await.unwrap();
+        }
+    }
+
+    async fn assert_blocked_for(throttle: &MemoryThrottle, key: &str, delay: Duration) {
+        let blocked_for = throttle.blocked_for(key).await.unwrap().unwrap();
+        assert!(blocked_for <= delay && blocked_for > delay - Duration::from_secs(1) / 10);
+    }
+
+    #[tokio::test]
+    async fn free_attempts_are_not_blocked() {
+        let throttle = throttle(Duration::from_secs(60));
+        assert_eq!(throttle.blocked_for("key").await.unwrap(), None);
+        fail(&throttle, "key", 1).await;
+        assert_eq!(throttle.blocked_for("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn delay_doubles_until_lockout() {
+        let throttle = throttle(Duration::from_secs(60));
+        fail(&throttle, "key", 2).await;
+        assert_blocked_for(&throttle, "key", Duration::from_secs(1)).await;
+        fail(&throttle, "key", 1).await;
+        assert_blocked_for(&throttle, "key", Duration::from_secs(2)).await;
+        fail(&throttle, "key", 1).await;
+        assert_blocked_for(&throttle, "key", Duration::from_secs(4)).await;
+        fail(&throttle, "key", 1).await;
+        assert_blocked_for(&throttle, "key", Duration::from_secs(60)).await;
+    }
+
+    #[tokio::test]
+    async fn keys_are_counted_separately() {
+        let throttle = throttle(Duration::from_secs(60));
+        fail(&throttle, "first", 5).await;
+        fail(&throttle, "second", 1).await;
+        assert_blocked_for(&throttle, "first", Duration::from_secs(60)).await;
+        assert_eq!(throttle.blocked_for("second").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reset_unblocks() {
+        let throttle = throttle(Duration::from_secs(60));
+        fail(&throttle, "key", 5).await;
+        throttle.reset("key").await.unwrap();
+        assert_eq!(throttle.blocked_for("key").await.unwrap(), None);
+        fail(&throttle, "key", 1).await;
+        assert_eq!(throttle.blocked_for("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn old_failures_are_forgotten() {
+        let throttle = throttle(Duration::ZERO);
+        fail(&throttle, "key", 5).await;
+        assert_eq!(throttle.blocked_for("key").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use sqlx::{Pool, Postgres};
+
+use super::{LoginThrottle, ThrottleConfig, ThrottleResult};
+
+/// Keeps the counters in `chat.login_throttle`, so that they are shared between
+/// all the instances of the server and survive restarts.
+pub struct PostgresThrottle {
+    db_pool: Pool<Postgres>,
+    config: ThrottleConfig,
+}
+
+impl PostgresThrottle {
+    pub fn new(db_pool: Pool<Postgres>, config: ThrottleConfig) -> Self {
+        PostgresThrottle { db_pool, config }
+    }
+}
+
+impl LoginThrottle for PostgresThrottle {
+    fn blocked_for<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, Option<Duration>> {
+        Box::pin(async move {
+            let result = sqlx::query!(
+                r#"
+                SELECT EXTRACT(EPOCH FROM blocked_until - NOW()::timestamp)::FLOAT8 AS "seconds!"
+                FROM chat.login_throttle
+                WHERE key = $1 AND blocked_until > NOW()::timestamp
+                "#,
+                key
+            )
+            .fetch_optional(&self.db_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            Ok(result.map(|row| Duration::from_secs_f64(row.seconds.max(0.0))))
+        })
+    }
+
+    fn record_failure<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, ()> {
+        Box::pin(async move {
+            let mut tx = self.db_pool.begin().await.map_err(|e| e.to_string())?;
+
+            let attempts = sqlx::query!(
+                "
+                INSERT INTO chat.login_throttle (key, failures, last_failure_at)
+                VALUES ($1, 1, NOW()::timestamp)
+                ON CONFLICT (key) DO UPDATE SET
+                    failures = CASE
+                        WHEN chat.login_throttle.last_failure_at < NOW()::timestamp - make_interval(secs => $2)
+                        THEN 1
+                        ELSE chat.login_throttle.failures + 1
+                    END,
+                    last_failure_at = NOW()::timestamp
+                RETURNING failures
+                ",
+                key,
+                self.config.forget_after.as_secs_f64()
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            let delay = self
+                .config
+                .delay_after(attempts.failures as u32)
+                .map(|delay| delay.as_secs_f64());
+
+            sqlx::query!(
+                "
+                UPDATE chat.login_throttle
+                SET blocked_until = NOW()::timestamp + make_interval(secs => $2)
+                WHERE key = $1
+                ",
+                key,
+                delay
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            tx.commit().await.map_err(|e| e.to_string())
+        })
+    }
+
+    fn reset<'a>(&'a self, key: &'a str) -> ThrottleResult<'a, ()> {
+        Box::pin(async move {
+            sqlx::query!("DELETE FROM chat.login_throttle WHERE key = $1", key)
+                .execute(&self.db_pool)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
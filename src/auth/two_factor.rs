@@ -0,0 +1,444 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, PgConnection, Pool, Postgres};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::AppState;
+
+use super::{
+    authentication::start_session,
+    hasher::verify_password,
+    jwt::decode_mfa_token,
+    registration::User,
+    secret::hash_secret,
+    throttle::{check_throttle, ip_key, record_failures, reset_account_throttle, user_key},
+};
+
+const ISSUER: &str = "Chat";
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODES_COUNT: usize = 10;
+
+fn build_totp(secret: &str, account_name: String) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP,
+        secret,
+        Some(ISSUER.to_string()),
+        account_name,
+    )
+    .ok()
+}
+
+/// Generates a recovery code in the format of `xxxxx-xxxxx`.
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().replace('-', "").to_lowercase()
+}
+
+/// Checks the TOTP code of the user, and remembers its time step,
+/// so that the same code cannot be used twice.
+/// Pending secrets, which are not enabled yet, are checked only if `include_pending` is set.
+async fn check_totp_code(
+    executor: &mut PgConnection,
+    user_id: Uuid,
+    code: &str,
+    include_pending: bool,
+) -> sqlx::Result<bool> {
+    let totp = sqlx::query!(
+        "
+        SELECT t.secret, t.last_used_step, u.email FROM chat.totp AS t
+        INNER JOIN chat.user AS u
+        ON u.id = t.user_id
+        WHERE t.user_id = $1 AND (t.enabled_at IS NOT NULL OR $2)
+        ",
+        user_id,
+        include_pending
+    )
+    .fetch_optional(&mut *executor)
+    .await?;
+
+    let totp_row = match totp {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+    let totp = match build_totp(&totp_row.secret, totp_row.email) {
+        Some(totp) => totp,
+        None => return Ok(false),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let current_step = now / TOTP_STEP;
+    let last_used_step = totp_row.last_used_step.unwrap_or(-1);
+
+    // Accepts the codes of the neighbouring steps as well to account for clock drift
+    for step in [current_step - 1, current_step, current_step + 1] {
+        if step as i64 <= last_used_step || totp.generate(step * TOTP_STEP) != code.trim() {
+            continue;
+        }
+
+        // Only one of the concurrent logins with the same code can move the step forward
+        let used = sqlx::query!(
+            "
+            UPDATE chat.totp SET last_used_step = $1
+            WHERE user_id = $2 AND (last_used_step IS NULL OR last_used_step < $1)
+            RETURNING user_id
+            ",
+            step as i64,
+            user_id
+        )
+        .fetch_optional(&mut *executor)
+        .await?;
+
+        return Ok(used.is_some());
+    }
+
+    Ok(false)
+}
+
+/// Checks either the TOTP code or one of the recovery codes of the user.
+/// Used recovery code cannot be used again.
+pub async fn check_second_factor(
+    executor: &Pool<Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> sqlx::Result<bool> {
+    let mut connection = executor.acquire().await?;
+
+    if check_totp_code(&mut connection, user_id, code, false).await? {
+        return Ok(true);
+    }
+
+    let recovery_code = sqlx::query!(
+        "
+        UPDATE chat.recovery_code SET used_at = NOW()::timestamp
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        RETURNING id
+        ",
+        user_id,
+        hash_secret(&normalize_recovery_code(code))
+    )
+    .fetch_optional(&mut *connection)
+    .await?;
+
+    Ok(recovery_code.is_some())
+}
+
+pub async fn is_two_factor_enabled(executor: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        "SELECT EXISTS (SELECT 1 FROM chat.totp WHERE user_id = $1 AND enabled_at IS NOT NULL)",
+        user_id
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(result.exists.unwrap_or(false))
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorSetup {
+    secret: String,
+    /// `otpauth://` URI to be shown as a QR code for authenticator apps.
+    provisioning_uri: String,
+}
+
+/// Generates a new TOTP secret for the user.
+/// Two-factor authentication stays disabled until the secret is confirmed with `enable_two_factor`.
+pub async fn setup_two_factor(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match is_two_factor_enabled(&state.db_pool, user.id).await {
+        Ok(enabled) if !enabled => {}
+        Ok(_) => {
+            return (
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+                .into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let totp = match build_totp(&secret, user.email) {
+        Some(totp) => totp,
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let insert_result = sqlx::query!(
+        "
+        INSERT INTO chat.totp (user_id, secret) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET secret = $2, last_used_step = NULL
+        ",
+        user.id,
+        secret
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match insert_result {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(TwoFactorSetup {
+                secret,
+                provisioning_uri: totp.get_url(),
+            }),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not set up two-factor authentication",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EnableTwoFactor {
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+/// Enables two-factor authentication once the user proves their authenticator app works.
+/// Returns the recovery codes, which are shown to the user only this time.
+pub async fn enable_two_factor(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<EnableTwoFactor>,
+) -> Response {
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match check_totp_code(&mut tx, user.id, &payload.code, true).await {
+        Ok(valid) if valid => {}
+        Ok(_) => return (StatusCode::FORBIDDEN, "Invalid code").into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let enable_result = sqlx::query!(
+        "UPDATE chat.totp SET enabled_at = NOW()::timestamp WHERE user_id = $1 AND enabled_at IS NULL RETURNING user_id",
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    if let Err(e) = enable_result {
+        return match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+                .into_response(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+    }
+
+    let delete_result = sqlx::query!("DELETE FROM chat.recovery_code WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await;
+
+    if delete_result.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_secret(&normalize_recovery_code(code)))
+        .collect();
+
+    let insert_result = sqlx::query!(
+        "INSERT INTO chat.recovery_code (user_id, code_hash) SELECT $1, UNNEST($2::VARCHAR[])",
+        user.id,
+        &code_hashes
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if insert_result.is_err() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not create recovery codes",
+        )
+            .into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => (StatusCode::OK, Json(RecoveryCodes { recovery_codes })).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not enable two-factor authentication",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactor {
+    password: String,
+    code: String,
+}
+
+pub async fn disable_two_factor(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DisableTwoFactor>,
+) -> Response {
+    // Shares the counter with `login_mfa`, so that a stolen access token doesn't allow guessing the codes here
+    let throttle_keys = [user_key(user.id)];
+    if let Some(response) = check_throttle(state.login_throttle.as_ref(), &throttle_keys).await {
+        return response;
+    }
+
+    match verify_password(&payload.password, &user.password) {
+        Ok(valid) if valid => {}
+        Ok(_) => {
+            record_failures(state.login_throttle.as_ref(), &throttle_keys).await;
+            return (StatusCode::FORBIDDEN, "Password isn't correct").into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    match check_second_factor(&state.db_pool, user.id, &payload.code).await {
+        Ok(valid) if valid => {}
+        Ok(_) => {
+            record_failures(state.login_throttle.as_ref(), &throttle_keys).await;
+            return (StatusCode::FORBIDDEN, "Invalid code").into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let delete_result = sqlx::query!("DELETE FROM chat.recovery_code WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await;
+
+    if delete_result.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let delete_result = sqlx::query!("DELETE FROM chat.totp WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await;
+
+    if delete_result.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            state.login_throttle.reset(&user_key(user.id)).await.ok();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not disable two-factor authentication",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginMfa {
+    mfa_token: String,
+    code: String,
+}
+
+/// Completes the login of the user with two-factor authentication enabled,
+/// using the token returned from `login` and either a TOTP or a recovery code.
+pub async fn login_mfa(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginMfa>,
+) -> Response {
+    let mfa_payload = match decode_mfa_token(&state.keys, &payload.mfa_token) {
+        Ok(payload) => payload,
+        Err(_) => return (StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response(),
+    };
+
+    let throttle_keys = [user_key(mfa_payload.id), ip_key(address.ip())];
+    if let Some(response) = check_throttle(state.login_throttle.as_ref(), &throttle_keys).await {
+        return response;
+    }
+
+    struct UserPayload {
+        id: Uuid,
+        username: String,
+        email: String,
+        token_version: i32,
+    }
+    let query_result = sqlx::query_as!(
+        UserPayload,
+        "SELECT id, username, email, token_version FROM chat.user WHERE id = $1 LIMIT 1",
+        mfa_payload.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let user = match query_result {
+        Ok(user) if user.token_version == mfa_payload.token_version => user,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            return (StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    match check_second_factor(&state.db_pool, user.id, &payload.code).await {
+        Ok(valid) if valid => {}
+        Ok(_) => {
+            record_failures(state.login_throttle.as_ref(), &throttle_keys).await;
+            return (StatusCode::UNAUTHORIZED, "Invalid code").into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    reset_account_throttle(state.login_throttle.as_ref(), &user.email, user.id).await;
+
+    start_session(
+        &state,
+        address,
+        &headers,
+        user.id,
+        user.username,
+        user.token_version,
+    )
+    .await
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use socketioxide::SocketIo;
+use sqlx::types::Uuid;
+
+use crate::{
+    mailer::{send_in_background, Mail},
+    sockets::disconnect_user,
+    AppState,
+};
+
+use super::{
+    jwt::{create_email_token, decode_email_token, EmailPayload, TokenType},
+    session::invalidate_user_tokens,
+};
+
+/// Sends the token which verifies that `email` belongs to the user.
+/// The token stops working as soon as the token version of the user changes.
+pub async fn send_verification_email(
+    state: &AppState,
+    user_id: Uuid,
+    email: String,
+    token_version: i32,
+) -> Result<(), String> {
+    let token = create_email_token(
+        &state.keys,
+        EmailPayload {
+            id: user_id,
+            email: email.clone(),
+            token_version,
+            token_type: TokenType::EmailVerification,
+        },
+        jwt_simple::prelude::Duration::from_days(1),
+    )
+    .map_err(|e| e.to_string())?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: email,
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "To verify your email, use the following token. It is valid for 24 hours.\n\n{token}"
+            ),
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmail {
+    token: String,
+}
+
+/// Marks the email of the user as verified.
+/// If the token was issued for a new email of the user, the email is changed to it,
+/// and all the sessions of the user are ended.
+pub async fn verify_email(
+    State(state): State<Arc<AppState>>,
+    Extension(io): Extension<SocketIo>,
+    Json(payload): Json<VerifyEmail>,
+) -> Response {
+    let email_payload =
+        match decode_email_token(&state.keys, &payload.token, TokenType::EmailVerification) {
+            Ok(payload) => payload,
+            Err(_) => {
+                return (StatusCode::BAD_REQUEST, "Invalid verification token").into_response()
+            }
+        };
+
+    struct UserPayload {
+        email: String,
+        token_version: i32,
+    }
+    let query_result = sqlx::query_as!(
+        UserPayload,
+        "SELECT email, token_version FROM chat.user WHERE id = $1 LIMIT 1",
+        email_payload.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let user = match query_result {
+        Ok(user) => user,
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                return (StatusCode::BAD_REQUEST, "Invalid verification token").into_response()
+            }
+            _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    };
+
+    if user.token_version != email_payload.token_version {
+        return (
+            StatusCode::BAD_REQUEST,
+            "This verification token has expired",
+        )
+            .into_response();
+    }
+
+    if user.email == email_payload.email {
+        let update_result = sqlx::query!(
+            "UPDATE chat.user SET email_verified_at = NOW()::timestamp WHERE id = $1 AND email_verified_at IS NULL",
+            email_payload.id
+        )
+        .execute(&state.db_pool)
+        .await;
+
+        return match update_result {
+            Ok(_) => StatusCode::NO_CONTENT.into_response(),
+            Err(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not verify your email due to internal reasons",
+            )
+                .into_response(),
+        };
+    }
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not change your email due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    let update_result = sqlx::query!(
+        "UPDATE chat.user SET email = $1, email_verified_at = NOW()::timestamp WHERE id = $2",
+        email_payload.email,
+        email_payload.id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(err) = update_result {
+        return match err {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                (StatusCode::CONFLICT, "This email is already used").into_response()
+            }
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not change your email due to internal reasons",
+            )
+                .into_response(),
+        };
+    }
+
+    if invalidate_user_tokens(&mut tx, email_payload.id)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not end your sessions",
+        )
+            .into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            disconnect_user(&io, email_payload.id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not change your email due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerification {
+    email: String,
+}
+
+/// Sends the verification token once more if the email isn't verified yet.
+/// Always responds the same way, so that it cannot be used to check which emails are registered.
+pub async fn resend_verification(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ResendVerification>,
+) -> Response {
+    struct UserPayload {
+        id: Uuid,
+        email: String,
+        token_version: i32,
+    }
+    let query_result = sqlx::query_as!(
+        UserPayload,
+        "SELECT id, email, token_version FROM chat.user WHERE email = $1 AND email_verified_at IS NULL LIMIT 1",
+        payload.email
+    )
+    .fetch_optional(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(Some(user)) => {
+            send_in_background(async move {
+                send_verification_email(&state, user.id, user.email, user.token_version).await
+            });
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(None) => StatusCode::ACCEPTED.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
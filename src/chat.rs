@@ -2,20 +2,54 @@ use std::sync::Arc;
 
 use axum::{
     middleware,
-    routing::{delete, get},
+    routing::{delete, get, patch, post},
     Router,
 };
-use chat::{create_chat, delete_chat, get_chats, rename_chat};
+use channel::{discover_chats, get_join_requests, join_chat};
+use chat::{change_chat_settings, create_chat, delete_chat, get_chat, get_chats, rename_chat};
+use direct::open_direct_chat;
+use invite::{create_invite, get_invites, redeem_invite, revoke_invite};
+use member::{change_member_role, get_members};
+use message::{get_messages, get_thread};
+use ownership::transfer_chat;
+use search::search_messages;
 
 use crate::middlewares::jwt_authorization;
 use crate::AppState;
 
+pub mod channel;
 pub mod chat;
+pub mod direct;
+pub mod invitation;
+pub mod invite;
+pub mod member;
+pub mod message;
+pub mod ownership;
+pub mod permission;
+pub mod reaction;
+pub mod search;
 
 pub fn routes(shared_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/", get(get_chats).post(create_chat))
-        .route("/:chat_id", delete(delete_chat).patch(rename_chat))
+        .route("/search", get(search_messages))
+        .route("/discover", get(discover_chats))
+        .route("/join/:code", post(redeem_invite))
+        .route("/direct/:user_id", post(open_direct_chat))
+        .route(
+            "/:chat_id",
+            get(get_chat).delete(delete_chat).patch(rename_chat),
+        )
+        .route("/:chat_id/settings", patch(change_chat_settings))
+        .route("/:chat_id/join", post(join_chat))
+        .route("/:chat_id/join-requests", get(get_join_requests))
+        .route("/:chat_id/members", get(get_members))
+        .route("/:chat_id/members/:user_id", patch(change_member_role))
+        .route("/:chat_id/messages", get(get_messages))
+        .route("/:chat_id/threads/:message_id", get(get_thread))
+        .route("/:chat_id/transfer", post(transfer_chat))
+        .route("/:chat_id/invites", get(get_invites).post(create_invite))
+        .route("/:chat_id/invites/:invite_id", delete(revoke_invite))
         .layer(middleware::from_fn_with_state(
             shared_state.clone(),
             jwt_authorization,
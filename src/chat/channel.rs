@@ -0,0 +1,308 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use socketioxide::SocketIo;
+use sqlx::{types::Uuid, Pool, Postgres};
+
+use crate::{auth::registration::User, sockets::user_room, AppState};
+
+use super::{
+    chat::{ChatKind, ChatVisibility},
+    invite::MemberJoined,
+    permission::{ChatPermission, ChatRole},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Serialize)]
+pub struct DiscoveredChat {
+    id: Uuid,
+    name: String,
+    visibility: ChatVisibility,
+    member_count: i64,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct DiscoverQuery {
+    /// Part of the chat name.
+    q: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Lists the chats which can be joined without an invitation, the most popular first.
+pub async fn discover_chats(
+    Query(query): Query<DiscoverQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Limit should be between 1 and {MAX_PAGE_SIZE}"),
+        )
+            .into_response();
+    }
+
+    // Wildcards typed by the user are matched literally
+    let pattern = query.q.map(|q| {
+        let escaped = q
+            .trim()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{escaped}%")
+    });
+
+    let query_result = sqlx::query_as!(
+        DiscoveredChat,
+        r#"
+        SELECT c.id, c.name, c.visibility AS "visibility: ChatVisibility", c.created_at,
+            (SELECT COUNT(*) FROM chat.user_chat AS uc WHERE uc.chat_id = c.id) AS "member_count!"
+        FROM chat.chat AS c
+        WHERE c.visibility <> 'private' AND c.kind = 'group'
+            AND ($1::text IS NULL OR c.name ILIKE $1)
+        ORDER BY "member_count!" DESC, c.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        pattern,
+        limit,
+        query.offset.unwrap_or(0).max(0)
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(chats) => (StatusCode::OK, Json(chats)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not find any chats due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[sqlx(type_name = "chat.join_request_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JoinRequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Serialize)]
+pub struct JoinRequest {
+    id: Uuid,
+    chat_id: Uuid,
+    user_id: Uuid,
+    username: String,
+    created_at: NaiveDateTime,
+}
+
+/// Joins a public chat right away, or asks the admins of a request-to-join chat to let the user in.
+pub async fn join_chat(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    struct JoinableChat {
+        kind: ChatKind,
+        visibility: ChatVisibility,
+    }
+    let chat_result = sqlx::query_as!(
+        JoinableChat,
+        r#"SELECT kind AS "kind: ChatKind", visibility AS "visibility: ChatVisibility" FROM chat.chat WHERE id = $1"#,
+        chat_id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let visibility = match chat_result {
+        Ok(chat) if chat.kind == ChatKind::Group => chat.visibility,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not find chat due to internal reasons",
+            )
+                .into_response()
+        }
+    };
+
+    match user.is_member(&state.db_pool, chat_id).await {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::CONFLICT, "You are already in this chat").into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    match visibility {
+        ChatVisibility::Private => (
+            StatusCode::FORBIDDEN,
+            "This chat can only be joined by invitation",
+        )
+            .into_response(),
+        ChatVisibility::Public => {
+            let insert_result = sqlx::query!(
+                "INSERT INTO chat.user_chat (user_id, chat_id, role) VALUES ($1, $2, $3)",
+                user.id,
+                chat_id,
+                ChatRole::Member as ChatRole
+            )
+            .execute(&state.db_pool)
+            .await;
+
+            match insert_result {
+                Ok(_) => {
+                    io.within(chat_id.to_string())
+                        .emit(
+                            "member-joined",
+                            MemberJoined {
+                                chat_id,
+                                user_id: user.id,
+                                username: user.username,
+                            },
+                        )
+                        .ok();
+                    StatusCode::NO_CONTENT.into_response()
+                }
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                    (StatusCode::CONFLICT, "You are already in this chat").into_response()
+                }
+                Err(_) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not join the chat due to internal reasons",
+                )
+                    .into_response(),
+            }
+        }
+        ChatVisibility::RequestToJoin => request_to_join(&state.db_pool, &io, user, chat_id).await,
+    }
+}
+
+async fn request_to_join(
+    executor: &Pool<Postgres>,
+    io: &SocketIo,
+    user: User,
+    chat_id: Uuid,
+) -> Response {
+    struct CreatedRequest {
+        id: Uuid,
+        created_at: NaiveDateTime,
+    }
+    let insert_result = sqlx::query_as!(
+        CreatedRequest,
+        "INSERT INTO chat.join_request (chat_id, user_id) VALUES ($1, $2) RETURNING id, created_at",
+        chat_id,
+        user.id
+    )
+    .fetch_one(executor)
+    .await;
+
+    let request = match insert_result {
+        Ok(request) => JoinRequest {
+            id: request.id,
+            chat_id,
+            user_id: user.id,
+            username: user.username,
+            created_at: request.created_at,
+        },
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return (
+                StatusCode::CONFLICT,
+                "You have already requested to join this chat",
+            )
+                .into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not request to join the chat due to internal reasons",
+            )
+                .into_response()
+        }
+    };
+
+    // The request is already saved, so the admins who aren't notified can still find it later
+    let admins = sqlx::query_scalar!(
+        "SELECT user_id FROM chat.user_chat WHERE chat_id = $1 AND role >= $2",
+        chat_id,
+        ChatRole::Admin as ChatRole
+    )
+    .fetch_all(executor)
+    .await
+    .unwrap_or_default();
+
+    if !admins.is_empty() {
+        let rooms: Vec<String> = admins.into_iter().map(user_room).collect();
+        io.within(rooms).emit("join-request", &request).ok();
+    }
+
+    (StatusCode::ACCEPTED, Json(request)).into_response()
+}
+
+/// Lists the pending join requests of the chat, the oldest first.
+pub async fn get_join_requests(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::ManageJoinRequests) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "Only admins of this chat can see its join requests",
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let query_result = sqlx::query_as!(
+        JoinRequest,
+        "
+        SELECT jr.id, jr.chat_id, jr.user_id, u.username, jr.created_at
+        FROM chat.join_request AS jr
+        INNER JOIN chat.user AS u
+        ON u.id = jr.user_id
+        WHERE jr.chat_id = $1 AND jr.status = 'pending'
+        ORDER BY jr.created_at
+        ",
+        chat_id
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(requests) => (StatusCode::OK, Json(requests)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not find the join requests due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+/// Sent to the requester once an admin has responded to their join request.
+#[derive(Serialize)]
+pub struct JoinRequestResolved {
+    pub request_id: Uuid,
+    pub chat_id: Uuid,
+    pub approved: bool,
+}
@@ -6,35 +6,83 @@ use axum::{
     response::{IntoResponse, Response},
     Extension, Json,
 };
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::{types::Uuid, Postgres};
 
 use crate::{auth::registration::User, AppState};
 
+use super::{
+    message::PREVIEW_LENGTH,
+    permission::{ChatPermission, ChatRole},
+};
+
+/// Group chats are created with `create_chat`, direct chats with `open_direct_chat`.
+#[derive(sqlx::Type, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[sqlx(type_name = "chat.chat_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatKind {
+    Group,
+    Direct,
+}
+
+/// Who can find the chat with `discover_chats` and join it without an invitation.
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[sqlx(type_name = "chat.chat_visibility", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatVisibility {
+    Private,
+    /// Anyone can read the history and join right away.
+    Public,
+    /// Joining has to be approved by an admin.
+    RequestToJoin,
+}
+
 #[derive(Deserialize)]
 pub struct CreateChat {
     name: String,
 }
 
+/// Checks the membership by the user id, for callers which don't have the whole `User`, e.g. sockets.
+pub async fn is_member(
+    executor: &sqlx::Pool<Postgres>,
+    user_id: Uuid,
+    chat_id: Uuid,
+) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2) AS "exists!""#,
+        user_id,
+        chat_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
 impl User {
-    pub async fn is_admin(
+    pub async fn is_member(
         &self,
         executor: &sqlx::Pool<Postgres>,
         chat_id: Uuid,
     ) -> sqlx::Result<bool> {
-        struct AdminId {
-            admin_id: Uuid,
-        }
+        is_member(executor, self.id, chat_id).await
+    }
 
-        let admin_id = sqlx::query_as!(
-            AdminId,
-            "SELECT admin_id FROM chat.chat WHERE id = $1;",
-            chat_id,
+    /// Members can read any chat, and everyone can read public chats.
+    pub async fn can_read(
+        &self,
+        executor: &sqlx::Pool<Postgres>,
+        chat_id: Uuid,
+    ) -> sqlx::Result<bool> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2)
+                OR EXISTS (SELECT 1 FROM chat.chat WHERE id = $2 AND visibility = 'public') AS "can_read!"
+            "#,
+            self.id,
+            chat_id
         )
         .fetch_one(executor)
-        .await?;
-
-        Ok(admin_id.admin_id == self.id)
+        .await
     }
 }
 
@@ -62,9 +110,8 @@ pub async fn create_chat(
     }
     let insertion_result = sqlx::query_as!(
         ChatId,
-        "INSERT INTO chat.chat (name, admin_id) VALUES ($1, $2) RETURNING id;",
-        payload.name,
-        user.id
+        "INSERT INTO chat.chat (name) VALUES ($1) RETURNING id;",
+        payload.name
     )
     .fetch_one(&mut *tx)
     .await;
@@ -76,12 +123,14 @@ pub async fn create_chat(
         }
     };
 
-    let insertion_result =
-        sqlx::query("INSERT INTO chat.user_chat (user_id, chat_id) VALUES ($1, $2);")
-            .bind(user.id)
-            .bind(chat_id.id)
-            .execute(&mut *tx)
-            .await;
+    let insertion_result = sqlx::query!(
+        "INSERT INTO chat.user_chat (user_id, chat_id, role) VALUES ($1, $2, $3);",
+        user.id,
+        chat_id.id,
+        ChatRole::Owner as ChatRole
+    )
+    .execute(&mut *tx)
+    .await;
 
     if let Err(_) = insertion_result {
         return (
@@ -99,35 +148,127 @@ pub async fn create_chat(
     }
 }
 
+/// Newest message of a chat, shown in the list of chats.
+#[derive(Serialize)]
+pub struct LastMessage {
+    id: Uuid,
+    user_id: Option<Uuid>,
+    username: Option<String>,
+    /// Shortened to the length of a preview.
+    content: String,
+    created_at: NaiveDateTime,
+}
+
 #[derive(Serialize)]
 pub struct Chat {
+    /// Username of the other party for direct chats.
     name: String,
     chat_id: Uuid,
-    admin_username: String,
+    kind: ChatKind,
+    /// `None` for direct chats, which have no admin.
+    admin_username: Option<String>,
+    /// Messages of other members after the last one the user has read.
+    unread_count: i64,
+    /// `None` if nothing has been sent to the chat yet.
+    last_message: Option<LastMessage>,
+}
+
+/// Chat with its last message joined to it, as it is selected from the database.
+struct ChatRow {
+    name: String,
+    chat_id: Uuid,
+    kind: ChatKind,
+    admin_username: Option<String>,
+    unread_count: i64,
+    last_message_id: Option<Uuid>,
+    last_message_user_id: Option<Uuid>,
+    last_message_username: Option<String>,
+    last_message_content: Option<String>,
+    last_message_at: Option<NaiveDateTime>,
+}
+
+impl From<ChatRow> for Chat {
+    fn from(row: ChatRow) -> Self {
+        let last_message = match (
+            row.last_message_id,
+            row.last_message_content,
+            row.last_message_at,
+        ) {
+            (Some(id), Some(content), Some(created_at)) => Some(LastMessage {
+                id,
+                user_id: row.last_message_user_id,
+                username: row.last_message_username,
+                content,
+                created_at,
+            }),
+            _ => None,
+        };
+        Chat {
+            name: row.name,
+            chat_id: row.chat_id,
+            kind: row.kind,
+            admin_username: row.admin_username,
+            unread_count: row.unread_count,
+            last_message,
+        }
+    }
 }
 
 pub async fn get_chats(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
+    // Both lateral joins are index scans over the messages of a single chat,
+    // so the list stays fast for users with many chats
     let query_result = sqlx::query_as!(
-        Chat,
-        "
-        SELECT c.name AS name, chat_id, u.username AS admin_username
+        ChatRow,
+        r#"
+        SELECT COALESCE(other.username, c.name) AS "name!", uc.chat_id, c.kind AS "kind: ChatKind",
+            u.username AS "admin_username?", unread.count AS "unread_count!",
+            last.id AS "last_message_id?", last.user_id AS "last_message_user_id?",
+            lu.username AS "last_message_username?", LEFT(last.content, $2) AS "last_message_content?",
+            last.created_at AS "last_message_at?"
         FROM chat.user_chat AS uc
         INNER JOIN chat.chat AS c
         ON uc.chat_id = c.id
-        INNER JOIN chat.user AS u
-        ON u.id = c.admin_id
+        LEFT JOIN chat.user_chat AS owner
+        ON owner.chat_id = c.id AND owner.role = 'owner'
+        LEFT JOIN chat.user AS u
+        ON u.id = owner.user_id
+        LEFT JOIN chat.direct_chat AS dc
+        ON dc.chat_id = c.id
+        LEFT JOIN chat.user AS other
+        ON other.id = CASE WHEN dc.first_user_id = $1 THEN dc.second_user_id ELSE dc.first_user_id END
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS count FROM chat.message AS m
+            WHERE m.chat_id = uc.chat_id AND m.user_id IS DISTINCT FROM $1
+                AND (m.created_at, m.id) > (
+                    COALESCE(uc.last_read_at, uc.joined_at),
+                    COALESCE(uc.last_read_message_id, '00000000-0000-0000-0000-000000000000')
+                )
+        ) AS unread
+        LEFT JOIN LATERAL (
+            SELECT m.id, m.user_id, m.content, m.created_at FROM chat.message AS m
+            WHERE m.chat_id = uc.chat_id
+            ORDER BY m.created_at DESC, m.id DESC
+            LIMIT 1
+        ) AS last ON TRUE
+        LEFT JOIN chat.user AS lu
+        ON lu.id = last.user_id
         WHERE uc.user_id = $1;
-        ",
-        user.id
+        "#,
+        user.id,
+        PREVIEW_LENGTH
     )
     .fetch_all(&state.db_pool)
     .await;
 
     match query_result {
-        Ok(chats) => (StatusCode::OK, Json(chats)).into_response(),
+        Ok(chats) => (
+            StatusCode::OK,
+            Json(chats.into_iter().map(Chat::from).collect::<Vec<_>>()),
+        )
+            .into_response(),
         Err(err) => match err {
             sqlx::Error::RowNotFound => {
                 (StatusCode::NOT_FOUND, "Could not find any chats").into_response()
@@ -146,27 +287,21 @@ pub async fn delete_chat(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
-    let is_chat_admin = user.is_admin(&state.db_pool, chat_id).await;
-
-    match is_chat_admin {
-        Ok(is_admin) => {
-            if !is_admin {
-                return (StatusCode::FORBIDDEN, "Only admin can delete the chat").into_response();
-            }
+    match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::DeleteChat) => {}
+        Ok(Some(_)) => {
+            return (StatusCode::FORBIDDEN, "Only owner can delete the chat").into_response();
+        }
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not find chat due to internal reasons",
+            )
+                .into_response()
         }
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => {
-                return (StatusCode::NOT_FOUND, "Could not find chat with such an id")
-                    .into_response()
-            }
-            _ => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Could not find chat due to internal reasons",
-                )
-                    .into_response()
-            }
-        },
     }
 
     let mut tx = match state.db_pool.begin().await {
@@ -200,9 +335,7 @@ pub async fn delete_chat(
             .into_response();
     }
 
-    let deletion_result = sqlx::query("DELETE FROM chat.chat WHERE id = $1 AND admin_id = $2;")
-        .bind(chat_id)
-        .bind(user.id)
+    let deletion_result = sqlx::query!("DELETE FROM chat.chat WHERE id = $1;", chat_id)
         .execute(&mut *tx)
         .await;
 
@@ -222,6 +355,73 @@ pub async fn delete_chat(
     }
 }
 
+#[derive(Serialize)]
+pub struct ChatDetails {
+    id: Uuid,
+    /// Username of the other party for direct chats.
+    name: String,
+    kind: ChatKind,
+    /// `None` for direct chats, which have no owner.
+    owner_id: Option<Uuid>,
+    owner_username: Option<String>,
+    created_at: NaiveDateTime,
+    member_count: i64,
+    invitations_required: bool,
+    visibility: ChatVisibility,
+}
+
+pub async fn get_chat(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match user.can_read(&state.db_pool, chat_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (StatusCode::FORBIDDEN, "You are not a member of this chat").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let query_result = sqlx::query_as!(
+        ChatDetails,
+        r#"
+        SELECT c.id, COALESCE(other.username, c.name) AS "name!", c.kind AS "kind: ChatKind",
+            owner.user_id AS "owner_id?", u.username AS "owner_username?", c.created_at,
+            (SELECT COUNT(*) FROM chat.user_chat AS uc WHERE uc.chat_id = c.id) AS "member_count!",
+            c.invitations_required, c.visibility AS "visibility: ChatVisibility"
+        FROM chat.chat AS c
+        LEFT JOIN chat.user_chat AS owner
+        ON owner.chat_id = c.id AND owner.role = 'owner'
+        LEFT JOIN chat.user AS u
+        ON u.id = owner.user_id
+        LEFT JOIN chat.direct_chat AS dc
+        ON dc.chat_id = c.id
+        LEFT JOIN chat.user AS other
+        ON other.id = CASE WHEN dc.first_user_id = $2 THEN dc.second_user_id ELSE dc.first_user_id END
+        WHERE c.id = $1
+        "#,
+        chat_id,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(chat) => (StatusCode::OK, Json(chat)).into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+            }
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not find chat due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
+
 #[derive(Deserialize)]
 pub struct RenameChat {
     new_name: String,
@@ -241,31 +441,25 @@ pub async fn rename_chat(
             .into_response();
     }
 
-    let is_chat_admin = user.is_admin(&state.db_pool, chat_id).await;
-
-    match is_chat_admin {
-        Ok(is_admin) => {
-            if !is_admin {
-                return (
-                    StatusCode::FORBIDDEN,
-                    "Only admin of this chat can change its name",
-                )
-                    .into_response();
-            }
+    match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::RenameChat) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "Only admins of this chat can change its name",
+            )
+                .into_response();
+        }
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not find chat due to internal problems",
+            )
+                .into_response()
         }
-        Err(err) => match err {
-            sqlx::Error::RowNotFound => {
-                return (StatusCode::NOT_FOUND, "Could not find chat with such an id")
-                    .into_response()
-            }
-            _ => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Could not find chat due to internal problems",
-                )
-                    .into_response()
-            }
-        },
     }
 
     let insert_result = sqlx::query!(
@@ -290,3 +484,62 @@ pub async fn rename_chat(
         },
     }
 }
+
+/// Settings which are left out stay unchanged.
+#[derive(Deserialize)]
+pub struct ChatSettings {
+    /// Whether added users have to accept an invitation before joining the chat.
+    invitations_required: Option<bool>,
+    visibility: Option<ChatVisibility>,
+}
+
+pub async fn change_chat_settings(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ChatSettings>,
+) -> Response {
+    match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::ChangeSettings) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "Only admins of this chat can change its settings",
+            )
+                .into_response();
+        }
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not find chat due to internal problems",
+            )
+                .into_response()
+        }
+    }
+
+    let update_result = sqlx::query!(
+        "
+        UPDATE chat.chat
+        SET invitations_required = COALESCE($1, invitations_required),
+            visibility = COALESCE($2, visibility)
+        WHERE id = $3
+        ",
+        payload.invitations_required,
+        payload.visibility as Option<ChatVisibility>,
+        chat_id
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match update_result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not update chat settings",
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use sqlx::{types::Uuid, Pool, Postgres};
+
+use crate::{auth::registration::User, AppState};
+
+use super::{chat::ChatKind, permission::ChatRole};
+
+/// Direct chats always have exactly two members, so nobody can be added to them.
+pub async fn is_direct_chat(executor: &Pool<Postgres>, chat_id: Uuid) -> sqlx::Result<bool> {
+    let kind = sqlx::query_scalar!(
+        r#"SELECT kind AS "kind: ChatKind" FROM chat.chat WHERE id = $1"#,
+        chat_id
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(kind == ChatKind::Direct)
+}
+
+async fn find_direct_chat(
+    executor: &Pool<Postgres>,
+    user_id: Uuid,
+    other_user_id: Uuid,
+) -> sqlx::Result<Option<Uuid>> {
+    sqlx::query_scalar!(
+        "
+        SELECT chat_id FROM chat.direct_chat
+        WHERE first_user_id = LEAST($1::uuid, $2::uuid) AND second_user_id = GREATEST($1::uuid, $2::uuid)
+        ",
+        user_id,
+        other_user_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+async fn create_direct_chat(
+    executor: &Pool<Postgres>,
+    user_id: Uuid,
+    other_user_id: Uuid,
+) -> sqlx::Result<Uuid> {
+    let mut tx = executor.begin().await?;
+
+    let chat_id = sqlx::query_scalar!(
+        "INSERT INTO chat.chat (name, kind) VALUES ('', $1) RETURNING id",
+        ChatKind::Direct as ChatKind
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "
+        INSERT INTO chat.direct_chat (chat_id, first_user_id, second_user_id)
+        VALUES ($1, LEAST($2::uuid, $3::uuid), GREATEST($2::uuid, $3::uuid))
+        ",
+        chat_id,
+        user_id,
+        other_user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Neither party is an owner, so that the chat can't be renamed or gain members
+    sqlx::query!(
+        "INSERT INTO chat.user_chat (user_id, chat_id, role) VALUES ($1, $3, $4), ($2, $3, $4)",
+        user_id,
+        other_user_id,
+        chat_id,
+        ChatRole::Member as ChatRole
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(chat_id)
+}
+
+/// Returns the id of the direct chat with the user, creating the chat if there is none yet.
+pub async fn open_direct_chat(
+    Path(other_user_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if other_user_id == user.id {
+        return (
+            StatusCode::BAD_REQUEST,
+            "You cannot open a direct chat with yourself",
+        )
+            .into_response();
+    }
+
+    match find_direct_chat(&state.db_pool, user.id, other_user_id).await {
+        Ok(Some(chat_id)) => return (StatusCode::OK, Json(chat_id)).into_response(),
+        Ok(None) => {}
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not find the chat due to internal reasons",
+            )
+                .into_response()
+        }
+    }
+
+    match create_direct_chat(&state.db_pool, user.id, other_user_id).await {
+        Ok(chat_id) => (StatusCode::CREATED, Json(chat_id)).into_response(),
+        Err(e) => match e {
+            // The other party has opened the same chat at the same time
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                match find_direct_chat(&state.db_pool, user.id, other_user_id).await {
+                    Ok(Some(chat_id)) => (StatusCode::OK, Json(chat_id)).into_response(),
+                    _ => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Could not open the chat due to internal reasons",
+                    )
+                        .into_response(),
+                }
+            }
+            sqlx::Error::Database(e) if e.is_foreign_key_violation() => {
+                (StatusCode::NOT_FOUND, "User with such an id does not exist").into_response()
+            }
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not open the chat due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{types::Uuid, Pool, Postgres};
+
+/// Pending invitation of a user to a chat, sent to the invitee as the `invitation` event.
+#[derive(Serialize)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub chat_name: String,
+    /// `None` if the inviter deleted their account.
+    pub inviter_id: Option<Uuid>,
+    pub inviter_username: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Whether users have to accept an invitation before they are added to the chat.
+pub async fn invitations_required(executor: &Pool<Postgres>, chat_id: Uuid) -> sqlx::Result<bool> {
+    sqlx::query_scalar!(
+        "SELECT invitations_required FROM chat.chat WHERE id = $1",
+        chat_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Creates a pending invitation.
+/// Fails with a unique violation if the invitee already has a pending invitation to the chat.
+pub async fn create_invitation(
+    executor: &Pool<Postgres>,
+    chat_id: Uuid,
+    inviter_id: Uuid,
+    invitee_id: Uuid,
+) -> sqlx::Result<Invitation> {
+    sqlx::query_as!(
+        Invitation,
+        r#"
+        WITH inserted AS (
+            INSERT INTO chat.invitation (chat_id, inviter_id, invitee_id) VALUES ($1, $2, $3)
+            RETURNING id, chat_id, inviter_id, created_at
+        )
+        SELECT i.id AS "id!", i.chat_id AS "chat_id!", c.name AS chat_name,
+            i.inviter_id, u.username AS "inviter_username?", i.created_at AS "created_at!"
+        FROM inserted AS i
+        INNER JOIN chat.chat AS c
+        ON c.id = i.chat_id
+        LEFT JOIN chat.user AS u
+        ON u.id = i.inviter_id
+        "#,
+        chat_id,
+        inviter_id,
+        invitee_id
+    )
+    .fetch_one(executor)
+    .await
+}
@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use socketioxide::SocketIo;
+use sqlx::types::Uuid;
+
+use crate::{auth::registration::User, AppState};
+
+use super::{
+    direct::is_direct_chat,
+    permission::{ChatPermission, ChatRole},
+};
+
+const CODE_LENGTH: usize = 10;
+
+fn generate_code() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CODE_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Checks that the user may manage the invites of the chat, and responds with an error otherwise.
+async fn check_invite_permission(state: &AppState, user: &User, chat_id: Uuid) -> Option<Response> {
+    match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::ManageInvites) => None,
+        Ok(Some(_)) => Some(
+            (
+                StatusCode::FORBIDDEN,
+                "Only admins of this chat can manage its invites",
+            )
+                .into_response(),
+        ),
+        Ok(None) => {
+            Some((StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response())
+        }
+        Err(_) => Some(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+#[derive(Serialize)]
+pub struct Invite {
+    id: Uuid,
+    code: String,
+    created_by: Option<Uuid>,
+    created_at: NaiveDateTime,
+    expires_at: Option<NaiveDateTime>,
+    max_uses: Option<i32>,
+    uses: i32,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInvite {
+    /// The invite never expires if not set.
+    expires_in_minutes: Option<i32>,
+    /// The invite can be used any number of times if not set.
+    max_uses: Option<i32>,
+}
+
+pub async fn create_invite(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateInvite>,
+) -> Response {
+    if payload
+        .expires_in_minutes
+        .is_some_and(|minutes| minutes < 1)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Invite should be valid for at least a minute",
+        )
+            .into_response();
+    }
+    if payload.max_uses.is_some_and(|max_uses| max_uses < 1) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Invite should be usable at least once",
+        )
+            .into_response();
+    }
+
+    if let Some(response) = check_invite_permission(&state, &user, chat_id).await {
+        return response;
+    }
+
+    match is_direct_chat(&state.db_pool, chat_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Users cannot be invited to a direct chat",
+            )
+                .into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let insert_result = sqlx::query_as!(
+        Invite,
+        "
+        INSERT INTO chat.invite (chat_id, code, created_by, expires_at, max_uses)
+        VALUES ($1, $2, $3, NOW()::timestamp + make_interval(mins => $4), $5)
+        RETURNING id, code, created_by, created_at, expires_at, max_uses, uses
+        ",
+        chat_id,
+        generate_code(),
+        user.id,
+        payload.expires_in_minutes,
+        payload.max_uses
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match insert_result {
+        Ok(invite) => (StatusCode::CREATED, Json(invite)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not create the invite due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+/// Lists the invites of the chat which can still be used.
+pub async fn get_invites(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if let Some(response) = check_invite_permission(&state, &user, chat_id).await {
+        return response;
+    }
+
+    let query_result = sqlx::query_as!(
+        Invite,
+        "
+        SELECT id, code, created_by, created_at, expires_at, max_uses, uses
+        FROM chat.invite
+        WHERE chat_id = $1 AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > NOW()::timestamp)
+            AND (max_uses IS NULL OR uses < max_uses)
+        ORDER BY created_at DESC
+        ",
+        chat_id
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(invites) => (StatusCode::OK, Json(invites)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not find the invites due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+pub async fn revoke_invite(
+    Path((chat_id, invite_id)): Path<(Uuid, Uuid)>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if let Some(response) = check_invite_permission(&state, &user, chat_id).await {
+        return response;
+    }
+
+    let revoke_result = sqlx::query!(
+        "
+        UPDATE chat.invite SET revoked_at = NOW()::timestamp
+        WHERE id = $1 AND chat_id = $2 AND revoked_at IS NULL
+        RETURNING id
+        ",
+        invite_id,
+        chat_id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match revoke_result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::NOT_FOUND,
+                "Could not find invite with such an id",
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not revoke the invite due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
+
+/// Sent to the room of the chat when a user joins it.
+#[derive(Serialize)]
+pub struct MemberJoined {
+    pub chat_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+/// Adds the user to the chat of the invite.
+pub async fn redeem_invite(
+    Path(code): Path<String>,
+    Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not join the chat due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    struct RedeemedInvite {
+        id: Uuid,
+        chat_id: Uuid,
+    }
+    let redeem_result = sqlx::query_as!(
+        RedeemedInvite,
+        "
+        UPDATE chat.invite SET uses = uses + 1
+        WHERE code = $1 AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > NOW()::timestamp)
+            AND (max_uses IS NULL OR uses < max_uses)
+        RETURNING id, chat_id
+        ",
+        code
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let invite = match redeem_result {
+        Ok(invite) => invite,
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                return (StatusCode::NOT_FOUND, "Invalid or expired invite").into_response()
+            }
+            _ => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not join the chat due to internal reasons",
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    let insert_result = sqlx::query!(
+        "INSERT INTO chat.user_chat (user_id, chat_id, role) VALUES ($1, $2, $3)",
+        user.id,
+        invite.chat_id,
+        ChatRole::Member as ChatRole
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(err) = insert_result {
+        return match err {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                (StatusCode::CONFLICT, "You are already in this chat").into_response()
+            }
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not join the chat due to internal reasons",
+            )
+                .into_response(),
+        };
+    }
+
+    let record_result = sqlx::query!(
+        "INSERT INTO chat.invite_redemption (invite_id, user_id) VALUES ($1, $2)",
+        invite.id,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if record_result.is_err() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not join the chat due to internal reasons",
+        )
+            .into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            io.within(invite.chat_id.to_string())
+                .emit(
+                    "member-joined",
+                    MemberJoined {
+                        chat_id: invite.chat_id,
+                        user_id: user.id,
+                        username: user.username,
+                    },
+                )
+                .ok();
+            (StatusCode::OK, Json(invite.chat_id)).into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not join the chat due to internal reasons",
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+
+use crate::{auth::registration::User, AppState};
+
+use super::permission::{ChatPermission, ChatRole};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Serialize)]
+pub struct ChatMember {
+    user_id: Uuid,
+    username: String,
+    joined_at: NaiveDateTime,
+    role: ChatRole,
+}
+
+#[derive(Deserialize)]
+pub struct MembersQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Lists the members of the chat in the order they joined it.
+pub async fn get_members(
+    Path(chat_id): Path<Uuid>,
+    Query(query): Query<MembersQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Limit should be between 1 and {MAX_PAGE_SIZE}"),
+        )
+            .into_response();
+    }
+
+    match user.is_member(&state.db_pool, chat_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (StatusCode::FORBIDDEN, "You are not a member of this chat").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let query_result = sqlx::query_as!(
+        ChatMember,
+        r#"
+        SELECT uc.user_id, u.username, uc.joined_at, uc.role AS "role: ChatRole"
+        FROM chat.user_chat AS uc
+        INNER JOIN chat.user AS u
+        ON u.id = uc.user_id
+        WHERE uc.chat_id = $1
+        ORDER BY uc.joined_at, uc.user_id
+        LIMIT $2 OFFSET $3
+        "#,
+        chat_id,
+        limit,
+        query.offset.unwrap_or(0).max(0)
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(members) => (StatusCode::OK, Json(members)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not find the members due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangeRole {
+    role: ChatRole,
+}
+
+/// Changes the role of a member.
+/// Only the members whose role is lower than yours can be changed, and only to a role lower than yours.
+/// The owner cannot be changed this way.
+pub async fn change_member_role(
+    Path((chat_id, member_id)): Path<(Uuid, Uuid)>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ChangeRole>,
+) -> Response {
+    let role = match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::ManageRoles) => role,
+        Ok(Some(_)) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "Only admins of this chat can change the roles",
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if payload.role >= role {
+        return (
+            StatusCode::FORBIDDEN,
+            "You can only give roles lower than yours",
+        )
+            .into_response();
+    }
+
+    let update_result = sqlx::query!(
+        "
+        UPDATE chat.user_chat SET role = $1
+        WHERE chat_id = $2 AND user_id = $3 AND role < $4
+        RETURNING user_id
+        ",
+        payload.role as ChatRole,
+        chat_id,
+        member_id,
+        role as ChatRole
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match update_result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::NOT_FOUND,
+                "Could not find a member with a role lower than yours",
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not change the role due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
@@ -0,0 +1,519 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, Pool, Postgres};
+
+use crate::{auth::registration::User, AppState};
+
+use super::reaction::{find_reactions, ReactionCount};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+/// How many characters of the quoted message are shown in a reply.
+pub const PREVIEW_LENGTH: i32 = 100;
+
+/// Shortened message which a reply quotes.
+#[derive(Serialize)]
+pub struct MessagePreview {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub content: String,
+}
+
+/// Finds the preview of a message, which is `None` if the message doesn't exist anymore.
+pub async fn find_preview(
+    executor: &Pool<Postgres>,
+    message_id: Uuid,
+) -> sqlx::Result<Option<MessagePreview>> {
+    sqlx::query_as!(
+        MessagePreview,
+        r#"
+        SELECT m.id, m.user_id, u.username AS "username?", LEFT(m.content, $2) AS "content!"
+        FROM chat.message AS m
+        LEFT JOIN chat.user AS u
+        ON u.id = m.user_id
+        WHERE m.id = $1
+        "#,
+        message_id,
+        PREVIEW_LENGTH
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Same as the messages sent through the sockets, with the username of the author.
+#[derive(Serialize)]
+pub struct HistoryMessage {
+    id: Uuid,
+    content: String,
+    /// `None` if the author deleted their account.
+    user_id: Option<Uuid>,
+    created_at: NaiveDateTime,
+    username: Option<String>,
+    reply_to_id: Option<Uuid>,
+    /// `None` unless the message is a reply.
+    thread_root_id: Option<Uuid>,
+    /// Number of replies in the thread started by the message.
+    reply_count: i64,
+    /// `None` if the message isn't a reply or the quoted message was deleted.
+    reply_to: Option<MessagePreview>,
+    reactions: Vec<ReactionCount>,
+}
+
+/// Message with the quoted message joined to it, as it is selected from the database.
+struct HistoryRow {
+    id: Uuid,
+    content: String,
+    user_id: Option<Uuid>,
+    created_at: NaiveDateTime,
+    username: Option<String>,
+    reply_to_id: Option<Uuid>,
+    thread_root_id: Option<Uuid>,
+    reply_count: i64,
+    quoted_user_id: Option<Uuid>,
+    quoted_username: Option<String>,
+    quoted_content: Option<String>,
+}
+
+impl From<HistoryRow> for HistoryMessage {
+    fn from(row: HistoryRow) -> Self {
+        let reply_to = match (row.reply_to_id, row.quoted_content) {
+            (Some(id), Some(content)) => Some(MessagePreview {
+                id,
+                user_id: row.quoted_user_id,
+                username: row.quoted_username,
+                content,
+            }),
+            _ => None,
+        };
+        HistoryMessage {
+            id: row.id,
+            content: row.content,
+            user_id: row.user_id,
+            created_at: row.created_at,
+            username: row.username,
+            reply_to_id: row.reply_to_id,
+            thread_root_id: row.thread_root_id,
+            reply_count: row.reply_count,
+            reply_to,
+            reactions: Vec::new(),
+        }
+    }
+}
+
+/// Fills in the reactions to the messages as the viewer sees them.
+async fn attach_reactions(
+    executor: &Pool<Postgres>,
+    messages: &mut [HistoryMessage],
+    viewer_id: Uuid,
+) -> sqlx::Result<()> {
+    let message_ids: Vec<Uuid> = messages.iter().map(|message| message.id).collect();
+    let mut reactions = find_reactions(executor, &message_ids, viewer_id).await?;
+    for message in messages {
+        message.reactions = reactions.remove(&message.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct MessagePage {
+    /// Ordered from the oldest to the newest.
+    messages: Vec<HistoryMessage>,
+    has_older: bool,
+    has_newer: bool,
+}
+
+/// Position of a message in the history, which the pages are counted from.
+struct Cursor {
+    created_at: NaiveDateTime,
+    id: Uuid,
+}
+
+/// Finds at most `limit` messages older than the cursor, or the newest ones if there is no cursor.
+/// Also returns whether there are more of them.
+async fn fetch_older(
+    executor: &Pool<Postgres>,
+    chat_id: Uuid,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> sqlx::Result<(Vec<HistoryMessage>, bool)> {
+    let mut messages = sqlx::query_as!(
+        HistoryRow,
+        r#"
+        SELECT m.id, m.content, m.user_id, m.created_at, u.username AS "username?",
+            m.reply_to_id, m.thread_root_id,
+            (SELECT COUNT(*) FROM chat.message AS r WHERE r.thread_root_id = m.id) AS "reply_count!",
+            q.user_id AS "quoted_user_id?", qu.username AS "quoted_username?",
+            LEFT(q.content, $5) AS "quoted_content?"
+        FROM chat.message AS m
+        LEFT JOIN chat.user AS u
+        ON u.id = m.user_id
+        LEFT JOIN chat.message AS q
+        ON q.id = m.reply_to_id
+        LEFT JOIN chat.user AS qu
+        ON qu.id = q.user_id
+        WHERE m.chat_id = $1
+            AND ($2::timestamp IS NULL OR (m.created_at, m.id) < ($2, $3))
+        ORDER BY m.created_at DESC, m.id DESC
+        LIMIT $4
+        "#,
+        chat_id,
+        cursor.map(|cursor| cursor.created_at),
+        cursor.map(|cursor| cursor.id),
+        limit + 1,
+        PREVIEW_LENGTH
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let has_more = messages.len() as i64 > limit;
+    messages.truncate(limit as usize);
+    messages.reverse();
+    Ok((messages.into_iter().map(Into::into).collect(), has_more))
+}
+
+/// Finds at most `limit` messages newer than the cursor, including the cursor itself if `inclusive`.
+/// Also returns whether there are more of them.
+async fn fetch_newer(
+    executor: &Pool<Postgres>,
+    chat_id: Uuid,
+    cursor: &Cursor,
+    inclusive: bool,
+    limit: i64,
+) -> sqlx::Result<(Vec<HistoryMessage>, bool)> {
+    let mut messages = sqlx::query_as!(
+        HistoryRow,
+        r#"
+        SELECT m.id, m.content, m.user_id, m.created_at, u.username AS "username?",
+            m.reply_to_id, m.thread_root_id,
+            (SELECT COUNT(*) FROM chat.message AS r WHERE r.thread_root_id = m.id) AS "reply_count!",
+            q.user_id AS "quoted_user_id?", qu.username AS "quoted_username?",
+            LEFT(q.content, $6) AS "quoted_content?"
+        FROM chat.message AS m
+        LEFT JOIN chat.user AS u
+        ON u.id = m.user_id
+        LEFT JOIN chat.message AS q
+        ON q.id = m.reply_to_id
+        LEFT JOIN chat.user AS qu
+        ON qu.id = q.user_id
+        WHERE m.chat_id = $1
+            AND ((m.created_at, m.id) > ($2, $3) OR ($4 AND m.id = $3))
+        ORDER BY m.created_at, m.id
+        LIMIT $5
+        "#,
+        chat_id,
+        cursor.created_at,
+        cursor.id,
+        inclusive,
+        limit + 1,
+        PREVIEW_LENGTH
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let has_more = messages.len() as i64 > limit;
+    messages.truncate(limit as usize);
+    Ok((messages.into_iter().map(Into::into).collect(), has_more))
+}
+
+/// Message ids which the page is anchored to. At most one of them can be used.
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    before: Option<Uuid>,
+    after: Option<Uuid>,
+    around: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+/// Returns a page of the chat history.
+/// Without an anchor the newest messages are returned.
+pub async fn get_messages(
+    Path(chat_id): Path<Uuid>,
+    Query(query): Query<HistoryQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Limit should be between 1 and {MAX_PAGE_SIZE}"),
+        )
+            .into_response();
+    }
+
+    let anchors = [query.before, query.after, query.around];
+    if anchors.iter().filter(|anchor| anchor.is_some()).count() > 1 {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Only one of before, after and around can be used at once",
+        )
+            .into_response();
+    }
+
+    match user.can_read(&state.db_pool, chat_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (StatusCode::FORBIDDEN, "You are not a member of this chat").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let cursor = match anchors.into_iter().flatten().next() {
+        Some(message_id) => {
+            let cursor_result = sqlx::query_as!(
+                Cursor,
+                "SELECT created_at, id FROM chat.message WHERE id = $1 AND chat_id = $2",
+                message_id,
+                chat_id
+            )
+            .fetch_one(&state.db_pool)
+            .await;
+
+            match cursor_result {
+                Ok(cursor) => Some(cursor),
+                Err(e) => match e {
+                    sqlx::Error::RowNotFound => {
+                        return (
+                            StatusCode::NOT_FOUND,
+                            "Could not find message with such an id in this chat",
+                        )
+                            .into_response()
+                    }
+                    _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                },
+            }
+        }
+        None => None,
+    };
+
+    let page_result = match (&cursor, query.after, query.around) {
+        (Some(cursor), Some(_), _) => fetch_newer(&state.db_pool, chat_id, cursor, false, limit)
+            .await
+            .map(|(messages, has_newer)| MessagePage {
+                messages,
+                has_older: true,
+                has_newer,
+            }),
+        (Some(cursor), _, Some(_)) => {
+            let older_limit = limit / 2;
+            let older = fetch_older(&state.db_pool, chat_id, Some(cursor), older_limit).await;
+            let newer =
+                fetch_newer(&state.db_pool, chat_id, cursor, true, limit - older_limit).await;
+
+            match (older, newer) {
+                (Ok((mut messages, has_older)), Ok((newer, has_newer))) => {
+                    messages.extend(newer);
+                    Ok(MessagePage {
+                        messages,
+                        has_older,
+                        has_newer,
+                    })
+                }
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            }
+        }
+        (cursor, _, _) => fetch_older(&state.db_pool, chat_id, cursor.as_ref(), limit)
+            .await
+            .map(|(messages, has_older)| MessagePage {
+                messages,
+                has_older,
+                // Without a cursor the newest messages are fetched
+                has_newer: cursor.is_some(),
+            }),
+    };
+
+    let page_result = match page_result {
+        Ok(mut page) => attach_reactions(&state.db_pool, &mut page.messages, user.id)
+            .await
+            .map(|_| page),
+        Err(e) => Err(e),
+    };
+
+    match page_result {
+        Ok(page) => (StatusCode::OK, Json(page)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not load the messages due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+/// Sent to the chat room as `thread-updated` whenever a reply is added to the thread or removed from it.
+#[derive(Serialize)]
+pub struct ThreadSummary {
+    pub chat_id: Uuid,
+    pub thread_root_id: Uuid,
+    pub reply_count: i64,
+    pub last_reply_at: Option<NaiveDateTime>,
+}
+
+pub async fn find_thread_summary(
+    executor: &Pool<Postgres>,
+    chat_id: Uuid,
+    thread_root_id: Uuid,
+) -> sqlx::Result<ThreadSummary> {
+    sqlx::query_as!(
+        ThreadSummary,
+        r#"
+        SELECT $1::uuid AS "chat_id!", $2::uuid AS "thread_root_id!",
+            COUNT(*) AS "reply_count!", MAX(created_at) AS last_reply_at
+        FROM chat.message
+        WHERE thread_root_id = $2
+        "#,
+        chat_id,
+        thread_root_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+#[derive(Serialize)]
+pub struct Thread {
+    root: HistoryMessage,
+    /// Ordered from the oldest to the newest.
+    replies: Vec<HistoryMessage>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ThreadQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Returns the thread which the message belongs to, with a page of its replies.
+pub async fn get_thread(
+    Path((chat_id, message_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<ThreadQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Limit should be between 1 and {MAX_PAGE_SIZE}"),
+        )
+            .into_response();
+    }
+
+    match user.can_read(&state.db_pool, chat_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (StatusCode::FORBIDDEN, "You are not a member of this chat").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    // Replies point to the root of their thread, so the whole thread is found from any of its messages
+    let root_result = sqlx::query_as!(
+        HistoryRow,
+        r#"
+        SELECT m.id, m.content, m.user_id, m.created_at, u.username AS "username?",
+            m.reply_to_id, m.thread_root_id,
+            (SELECT COUNT(*) FROM chat.message AS r WHERE r.thread_root_id = m.id) AS "reply_count!",
+            q.user_id AS "quoted_user_id?", qu.username AS "quoted_username?",
+            LEFT(q.content, $3) AS "quoted_content?"
+        FROM chat.message AS m
+        LEFT JOIN chat.user AS u
+        ON u.id = m.user_id
+        LEFT JOIN chat.message AS q
+        ON q.id = m.reply_to_id
+        LEFT JOIN chat.user AS qu
+        ON qu.id = q.user_id
+        WHERE m.chat_id = $1 AND m.id = (
+            SELECT COALESCE(thread_root_id, id) FROM chat.message WHERE id = $2 AND chat_id = $1
+        )
+        "#,
+        chat_id,
+        message_id,
+        PREVIEW_LENGTH
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let root = match root_result {
+        Ok(root) => HistoryMessage::from(root),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    "Could not find message with such an id in this chat",
+                )
+                    .into_response()
+            }
+            _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    };
+
+    let replies_result = sqlx::query_as!(
+        HistoryRow,
+        r#"
+        SELECT m.id, m.content, m.user_id, m.created_at, u.username AS "username?",
+            m.reply_to_id, m.thread_root_id,
+            (SELECT COUNT(*) FROM chat.message AS r WHERE r.thread_root_id = m.id) AS "reply_count!",
+            q.user_id AS "quoted_user_id?", qu.username AS "quoted_username?",
+            LEFT(q.content, $4) AS "quoted_content?"
+        FROM chat.message AS m
+        LEFT JOIN chat.user AS u
+        ON u.id = m.user_id
+        LEFT JOIN chat.message AS q
+        ON q.id = m.reply_to_id
+        LEFT JOIN chat.user AS qu
+        ON qu.id = q.user_id
+        WHERE m.thread_root_id = $1
+        ORDER BY m.created_at, m.id
+        LIMIT $2 OFFSET $3
+        "#,
+        root.id,
+        limit + 1,
+        query.offset.unwrap_or(0).max(0),
+        PREVIEW_LENGTH
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match replies_result {
+        Ok(mut replies) => {
+            let has_more = replies.len() as i64 > limit;
+            replies.truncate(limit as usize);
+
+            let mut messages: Vec<HistoryMessage> = std::iter::once(root)
+                .chain(replies.into_iter().map(Into::into))
+                .collect();
+            if attach_reactions(&state.db_pool, &mut messages, user.id)
+                .await
+                .is_err()
+            {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not load the thread due to internal reasons",
+                )
+                    .into_response();
+            }
+
+            let replies = messages.split_off(1);
+            let thread = Thread {
+                root: messages.remove(0),
+                replies,
+                has_more,
+            };
+            (StatusCode::OK, Json(thread)).into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not load the thread due to internal reasons",
+        )
+            .into_response(),
+    }
+}
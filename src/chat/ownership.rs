@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use socketioxide::SocketIo;
+use sqlx::{types::Uuid, Pool, Postgres};
+
+use crate::{auth::registration::User, AppState};
+
+use super::permission::ChatRole;
+
+/// Sent to the room of the chat once it has a new owner.
+#[derive(Serialize)]
+pub struct OwnershipTransferred {
+    pub chat_id: Uuid,
+    pub previous_owner_id: Uuid,
+    pub new_owner_id: Uuid,
+}
+
+/// Makes the member the owner of the chat, and the previous owner an admin.
+/// Fails with `RowNotFound` if `owner_id` doesn't own the chat or `new_owner_id` isn't its member.
+pub async fn transfer_ownership(
+    executor: &Pool<Postgres>,
+    chat_id: Uuid,
+    owner_id: Uuid,
+    new_owner_id: Uuid,
+) -> sqlx::Result<OwnershipTransferred> {
+    let mut tx = executor.begin().await?;
+
+    // Demoted first, as a chat can have only one owner at a time
+    sqlx::query!(
+        "
+        UPDATE chat.user_chat SET role = $1
+        WHERE chat_id = $2 AND user_id = $3 AND role = 'owner'
+        RETURNING user_id
+        ",
+        ChatRole::Admin as ChatRole,
+        chat_id,
+        owner_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "
+        UPDATE chat.user_chat SET role = $1
+        WHERE chat_id = $2 AND user_id = $3
+        RETURNING user_id
+        ",
+        ChatRole::Owner as ChatRole,
+        chat_id,
+        new_owner_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(OwnershipTransferred {
+        chat_id,
+        previous_owner_id: owner_id,
+        new_owner_id,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct TransferOwnership {
+    user_id: Uuid,
+}
+
+/// Hands the chat over to another member.
+/// Afterwards the previous owner stays in the chat as an admin, and may leave it.
+pub async fn transfer_chat(
+    Path(chat_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<TransferOwnership>,
+) -> Response {
+    if payload.user_id == user.id {
+        return (StatusCode::BAD_REQUEST, "You already own this chat").into_response();
+    }
+
+    match user.chat_role(&state.db_pool, chat_id).await {
+        Ok(Some(ChatRole::Owner)) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "Only owner can transfer the ownership of the chat",
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "Could not find chat with such an id").into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    match transfer_ownership(&state.db_pool, chat_id, user.id, payload.user_id).await {
+        Ok(transferred) => {
+            io.within(chat_id.to_string())
+                .emit("ownership-transferred", transferred)
+                .ok();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::BAD_REQUEST,
+                "New owner has to be a member of the chat",
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not transfer the chat due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, Pool, Postgres};
+
+use crate::auth::registration::User;
+
+/// Role of a member in `chat.user_chat`, from the least to the most privileged.
+#[derive(
+    sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+#[sqlx(type_name = "chat.chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRole {
+    ReadOnly,
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+/// Actions in a chat which not every member may do.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChatPermission {
+    SendMessage,
+    React,
+    AddMember,
+    DeleteAnyMessage,
+    RemoveMember,
+    RenameChat,
+    ManageRoles,
+    ManageInvites,
+    ChangeSettings,
+    ManageJoinRequests,
+    DeleteChat,
+}
+
+impl ChatRole {
+    /// The least privileged role which has the permission.
+    fn required_for(permission: ChatPermission) -> ChatRole {
+        match permission {
+            ChatPermission::SendMessage | ChatPermission::React | ChatPermission::AddMember => {
+                ChatRole::Member
+            }
+            ChatPermission::DeleteAnyMessage => ChatRole::Moderator,
+            ChatPermission::RemoveMember
+            | ChatPermission::RenameChat
+            | ChatPermission::ManageRoles
+            | ChatPermission::ManageInvites
+            | ChatPermission::ChangeSettings
+            | ChatPermission::ManageJoinRequests => ChatRole::Admin,
+            ChatPermission::DeleteChat => ChatRole::Owner,
+        }
+    }
+
+    pub fn can(&self, permission: ChatPermission) -> bool {
+        *self >= ChatRole::required_for(permission)
+    }
+}
+
+impl User {
+    /// Returns `None` if the user isn't a member of the chat.
+    pub async fn chat_role(
+        &self,
+        executor: &Pool<Postgres>,
+        chat_id: Uuid,
+    ) -> sqlx::Result<Option<ChatRole>> {
+        sqlx::query_scalar!(
+            r#"SELECT role AS "role: ChatRole" FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2"#,
+            self.id,
+            chat_id
+        )
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Whether the user is a member of the chat with a role which has the permission.
+    pub async fn has_permission(
+        &self,
+        executor: &Pool<Postgres>,
+        chat_id: Uuid,
+        permission: ChatPermission,
+    ) -> sqlx::Result<bool> {
+        Ok(self
+            .chat_role(executor, chat_id)
+            .await?
+            .is_some_and(|role| role.can(permission)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLES: [ChatRole; 5] = [
+        ChatRole::ReadOnly,
+        ChatRole::Member,
+        ChatRole::Moderator,
+        ChatRole::Admin,
+        ChatRole::Owner,
+    ];
+
+    /// Checks that exactly the roles from `least_privileged` up have the permission.
+    fn assert_granted_from(permission: ChatPermission, least_privileged: ChatRole) {
+        for role in ROLES {
+            assert_eq!(
+                role.can(permission),
+                role >= least_privileged,
+                "{role:?} with {permission:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn members_can_write_and_add_members() {
+        assert_granted_from(ChatPermission::SendMessage, ChatRole::Member);
+        assert_granted_from(ChatPermission::React, ChatRole::Member);
+        assert_granted_from(ChatPermission::AddMember, ChatRole::Member);
+    }
+
+    #[test]
+    fn moderators_can_delete_any_message() {
+        assert_granted_from(ChatPermission::DeleteAnyMessage, ChatRole::Moderator);
+    }
+
+    #[test]
+    fn admins_manage_the_chat() {
+        for permission in [
+            ChatPermission::RemoveMember,
+            ChatPermission::RenameChat,
+            ChatPermission::ManageRoles,
+            ChatPermission::ManageInvites,
+            ChatPermission::ChangeSettings,
+            ChatPermission::ManageJoinRequests,
+        ] {
+            assert_granted_from(permission, ChatRole::Admin);
+        }
+    }
+
+    #[test]
+    fn only_owner_can_delete_chat() {
+        assert_granted_from(ChatPermission::DeleteChat, ChatRole::Owner);
+    }
+}
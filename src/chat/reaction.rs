@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::{types::Uuid, Pool, Postgres};
+
+/// Longest emoji which can be stored in `chat.reaction`.
+pub const MAX_EMOJI_LENGTH: usize = 32;
+
+const VARIATION_SELECTOR: char = '\u{FE0F}';
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const KEYCAP: char = '\u{20E3}';
+const CANCEL_TAG: char = '\u{E007F}';
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+fn is_tag(c: char) -> bool {
+    ('\u{E0020}'..='\u{E007E}').contains(&c)
+}
+
+/// Characters which are drawn as emoji on their own or with the variation selector.
+fn is_pictographic(c: char) -> bool {
+    matches!(
+        c,
+        '\u{A9}'
+            | '\u{AE}'
+            | '\u{203C}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{2199}'
+            | '\u{21A9}'..='\u{21AA}'
+            | '\u{231A}'..='\u{231B}'
+            | '\u{2328}'
+            | '\u{23CF}'
+            | '\u{23E9}'..='\u{23F3}'
+            | '\u{23F8}'..='\u{23FA}'
+            | '\u{24C2}'
+            | '\u{25AA}'..='\u{25AB}'
+            | '\u{25B6}'
+            | '\u{25C0}'
+            | '\u{25FB}'..='\u{25FE}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2934}'..='\u{2935}'
+            | '\u{2B05}'..='\u{2B07}'
+            | '\u{2B1B}'..='\u{2B1C}'
+            | '\u{2B50}'
+            | '\u{2B55}'
+            | '\u{3030}'
+            | '\u{303D}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1F000}'..='\u{1FAFF}'
+    ) && !is_regional_indicator(c)
+        && !is_skin_tone(c)
+}
+
+/// Checks that the reaction is exactly one emoji: a flag, a keycap,
+/// or pictographs joined by zero width joiners, each with an optional skin tone.
+pub fn is_single_emoji(emoji: &str) -> bool {
+    if emoji.len() > MAX_EMOJI_LENGTH {
+        return false;
+    }
+
+    let chars: Vec<char> = emoji.chars().collect();
+    match chars.as_slice() {
+        [first, second] if is_regional_indicator(*first) && is_regional_indicator(*second) => {
+            return true
+        }
+        [key, VARIATION_SELECTOR, KEYCAP] | [key, KEYCAP]
+            if key.is_ascii_digit() || *key == '#' || *key == '*' =>
+        {
+            return true
+        }
+        _ => {}
+    }
+
+    let mut chars = chars.into_iter().peekable();
+    loop {
+        if !chars.next().is_some_and(is_pictographic) {
+            return false;
+        }
+        chars.next_if_eq(&VARIATION_SELECTOR);
+        chars.next_if(|c| is_skin_tone(*c));
+
+        // Tag sequences, e.g. the flags of subdivisions
+        let mut tagged = false;
+        while chars.next_if(|c| is_tag(*c)).is_some() {
+            tagged = true;
+        }
+        if tagged && chars.next() != Some(CANCEL_TAG) {
+            return false;
+        }
+
+        match chars.next() {
+            None => return true,
+            Some(ZERO_WIDTH_JOINER) => continue,
+            Some(_) => return false,
+        }
+    }
+}
+
+/// Number of users who reacted to a message with the emoji.
+#[derive(Serialize, Clone)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// Whether the user who receives the message is one of them.
+    pub reacted: bool,
+}
+
+/// Finds the reactions to each of the messages, from the earliest added emoji.
+/// Messages without reactions are left out.
+pub async fn find_reactions(
+    executor: &Pool<Postgres>,
+    message_ids: &[Uuid],
+    viewer_id: Uuid,
+) -> sqlx::Result<HashMap<Uuid, Vec<ReactionCount>>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT message_id, emoji, COUNT(*) AS "count!", BOOL_OR(user_id = $2) AS "reacted!"
+        FROM chat.reaction
+        WHERE message_id = ANY($1)
+        GROUP BY message_id, emoji
+        ORDER BY message_id, MIN(created_at)
+        "#,
+        message_ids,
+        viewer_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let mut reactions: HashMap<Uuid, Vec<ReactionCount>> = HashMap::new();
+    for row in rows {
+        reactions
+            .entry(row.message_id)
+            .or_default()
+            .push(ReactionCount {
+                emoji: row.emoji,
+                count: row.count,
+                reacted: row.reacted,
+            });
+    }
+    Ok(reactions)
+}
+
+/// Users who reacted to the message with each emoji, from the earliest added one.
+pub struct Reactors {
+    pub emoji: String,
+    pub user_ids: Vec<Uuid>,
+}
+
+impl Reactors {
+    pub fn count_for(&self, viewer_id: Option<Uuid>) -> ReactionCount {
+        ReactionCount {
+            emoji: self.emoji.clone(),
+            count: self.user_ids.len() as i64,
+            reacted: viewer_id.is_some_and(|viewer_id| self.user_ids.contains(&viewer_id)),
+        }
+    }
+}
+
+pub async fn find_reactors(
+    executor: &Pool<Postgres>,
+    message_id: Uuid,
+) -> sqlx::Result<Vec<Reactors>> {
+    sqlx::query_as!(
+        Reactors,
+        r#"
+        SELECT emoji, ARRAY_AGG(user_id ORDER BY created_at) AS "user_ids!"
+        FROM chat.reaction
+        WHERE message_id = $1
+        GROUP BY emoji
+        ORDER BY MIN(created_at)
+        "#,
+        message_id
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_single_emoji() {
+        for emoji in [
+            "👍",
+            "❤️",
+            "👍🏽",
+            "🇺🇦",
+            "1️⃣",
+            "#⃣",
+            "👩‍👩‍👧‍👦",
+            "🧑🏿‍🚀",
+            "🏳️‍🌈",
+            "🏴\u{E0067}\u{E0062}\u{E0073}\u{E0063}\u{E0074}\u{E007F}",
+        ] {
+            assert!(is_single_emoji(emoji), "{emoji} should be accepted");
+        }
+    }
+
+    #[test]
+    fn rejects_everything_else() {
+        for emoji in [
+            "",
+            "a",
+            "1",
+            "👍👍",
+            "👍 ",
+            "👍a",
+            "🇺",
+            "🏽",
+            "\u{200D}",
+            "👍\u{200D}",
+            "<b>",
+        ] {
+            assert!(!is_single_emoji(emoji), "{emoji:?} should be rejected");
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+
+use crate::{auth::registration::User, AppState};
+
+const DEFAULT_RESULTS: i64 = 20;
+const MAX_RESULTS: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    chat_id: Option<Uuid>,
+    /// Author of the messages.
+    user_id: Option<Uuid>,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    id: Uuid,
+    chat_id: Uuid,
+    /// Username of the other party for direct chats.
+    chat_name: String,
+    user_id: Option<Uuid>,
+    username: Option<String>,
+    created_at: NaiveDateTime,
+    /// HTML-escaped part of the message with the matched words wrapped into `<mark>` tags.
+    snippet: String,
+    rank: f32,
+}
+
+/// Searches the messages of all the chats the user is a member of.
+/// The best matches come first.
+pub async fn search_messages(
+    Query(query): Query<SearchQuery>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if query.q.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "Search query cannot be empty").into_response();
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_RESULTS);
+    if !(1..=MAX_RESULTS).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Limit should be between 1 and {MAX_RESULTS}"),
+        )
+            .into_response();
+    }
+
+    let query_result = sqlx::query_as!(
+        SearchResult,
+        r#"
+        SELECT m.id, m.chat_id, COALESCE(other.username, c.name) AS "chat_name!", m.user_id,
+            u.username AS "username?", m.created_at,
+            ts_headline('english',
+                -- Escaped before highlighting, so that the only markup in the snippet is the highlight itself
+                replace(replace(replace(replace(m.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'),
+                q.query, 'StartSel=<mark>, StopSel=</mark>, MaxFragments=2') AS "snippet!",
+            ts_rank(m.search_vector, q.query) AS "rank!"
+        FROM chat.message AS m
+        CROSS JOIN websearch_to_tsquery('english', $2) AS q(query)
+        INNER JOIN chat.user_chat AS uc
+        ON uc.chat_id = m.chat_id AND uc.user_id = $1
+        INNER JOIN chat.chat AS c
+        ON c.id = m.chat_id
+        LEFT JOIN chat.user AS u
+        ON u.id = m.user_id
+        LEFT JOIN chat.direct_chat AS dc
+        ON dc.chat_id = c.id
+        LEFT JOIN chat.user AS other
+        ON other.id = CASE WHEN dc.first_user_id = $1 THEN dc.second_user_id ELSE dc.first_user_id END
+        WHERE m.search_vector @@ q.query
+            AND ($3::uuid IS NULL OR m.chat_id = $3)
+            AND ($4::uuid IS NULL OR m.user_id = $4)
+            AND ($5::timestamp IS NULL OR m.created_at >= $5)
+            AND ($6::timestamp IS NULL OR m.created_at < $6)
+        ORDER BY "rank!" DESC, m.created_at DESC
+        LIMIT $7 OFFSET $8
+        "#,
+        user.id,
+        query.q,
+        query.chat_id,
+        query.user_id,
+        query.from,
+        query.to,
+        limit,
+        query.offset.unwrap_or(0).max(0)
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(results) => (StatusCode::OK, Json(results)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not search the messages due to internal reasons",
+        )
+            .into_response(),
+    }
+}
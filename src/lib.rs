@@ -1,13 +1,22 @@
+use auth::{hasher::PasswordHasher, keys::KeyStore, throttle::LoginThrottle};
+use mailer::Mailer;
+use sockets::presence::PresenceTracker;
 use sqlx::{Pool, Postgres};
 
 pub mod auth;
 pub mod chat;
+pub mod mailer;
 pub mod middlewares;
 pub mod sockets;
 pub mod user;
 
 pub struct AppState {
     pub db_pool: Pool<Postgres>,
+    pub mailer: Box<dyn Mailer>,
+    pub login_throttle: Box<dyn LoginThrottle>,
+    pub keys: KeyStore,
+    pub password_hasher: Box<dyn PasswordHasher>,
+    pub presence: PresenceTracker,
 }
 
 pub async fn init_db() -> Pool<Postgres> {
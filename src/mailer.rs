@@ -0,0 +1,39 @@
+use std::{future::Future, pin::Pin};
+
+use file::FileMailer;
+use smtp::SmtpMailer;
+
+pub mod file;
+pub mod smtp;
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub type SendResult<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// Delivers emails to the users.
+/// Returns the reason of the failure as an error.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: Mail) -> SendResult<'_>;
+}
+
+/// Sends the mail without making the response wait for it, so that a slow or failing mailer doesn't fail the request.
+/// It also keeps the response time of endpoints which respond the same way whether the account exists
+/// from revealing the account. Failures are dropped, the user can ask for the mail again.
+pub fn send_in_background(sending: impl Future<Output = Result<(), String>> + Send + 'static) {
+    tokio::spawn(async move {
+        sending.await.ok();
+    });
+}
+
+/// Creates the SMTP mailer if `SMTP_HOST` is declared.
+/// Otherwise, the mails are written into `MAIL_DIR`, or to stdout if it isn't declared either.
+pub fn init_mailer() -> Box<dyn Mailer> {
+    match std::env::var("SMTP_HOST") {
+        Ok(host) => Box::new(SmtpMailer::new(&host).expect("Could not configure SMTP mailer")),
+        Err(_) => Box::new(FileMailer::new(std::env::var("MAIL_DIR").ok())),
+    }
+}
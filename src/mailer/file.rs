@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+use tokio::io::AsyncWriteExt;
+
+use super::{Mail, Mailer, SendResult};
+
+/// Mailer for tests and local development.
+/// Writes every mail into a separate file in the directory, or to stdout if there is none.
+pub struct FileMailer {
+    dir: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn new(dir: Option<impl Into<PathBuf>>) -> Self {
+        FileMailer {
+            dir: dir.map(Into::into),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: Mail) -> SendResult<'_> {
+        Box::pin(async move {
+            let content = format!(
+                "To: {}\nSubject: {}\n\n{}\n",
+                mail.to, mail.subject, mail.body
+            );
+
+            match &self.dir {
+                Some(dir) => {
+                    tokio::fs::create_dir_all(dir)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    // The address comes from the user, so it is kept out of the path
+                    let file_name = format!(
+                        "{}-{:016x}.eml",
+                        Utc::now().format("%Y%m%d%H%M%S%.f"),
+                        rand::random::<u64>()
+                    );
+                    tokio::fs::write(dir.join(file_name), content)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                None => tokio::io::stdout()
+                    .write_all(content.as_bytes())
+                    .await
+                    .map_err(|e| e.to_string()),
+            }
+        })
+    }
+}
@@ -0,0 +1,50 @@
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use super::{Mail, Mailer, SendResult};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl SmtpMailer {
+    /// Configures the transport from `SMTP_USERNAME`, `SMTP_PASSWORD` and `SMTP_FROM`.
+    pub fn new(host: &str) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(host)?;
+
+        if let (Ok(username), Ok(password)) = (
+            std::env::var("SMTP_USERNAME"),
+            std::env::var("SMTP_PASSWORD"),
+        ) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from: std::env::var("SMTP_FROM").expect("SMTP_FROM have to be defined"),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: Mail) -> SendResult<'_> {
+        Box::pin(async move {
+            let message = Message::builder()
+                .from(self.from.parse().map_err(|_| "Invalid sender address")?)
+                .to(mail.to.parse().map_err(|_| "Invalid recipient address")?)
+                .subject(mail.subject)
+                .header(ContentType::TEXT_PLAIN)
+                .body(mail.body)
+                .map_err(|e| e.to_string())?;
+
+            self.transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
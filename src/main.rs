@@ -1,16 +1,34 @@
-use axum::Router;
+use axum::{routing::get, Extension, Router};
 use dotenv::dotenv;
 use socketioxide::SocketIo;
-use std::{error::Error, sync::Arc};
+use std::{error::Error, net::SocketAddr, sync::Arc};
 
-use chat_backend::{auth, chat, init_db, sockets::on_connect, user, AppState};
+use chat_backend::{
+    auth::{
+        self,
+        hasher::init_password_hasher,
+        keys::{get_jwks, KeyStore},
+        throttle::init_login_throttle,
+    },
+    chat, init_db,
+    mailer::init_mailer,
+    sockets::{on_connect, presence::PresenceTracker},
+    user, AppState,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
 
     let db_pool = init_db().await;
-    let shared_state = Arc::new(AppState { db_pool });
+    let shared_state = Arc::new(AppState {
+        login_throttle: init_login_throttle(db_pool.clone()),
+        db_pool,
+        mailer: init_mailer(),
+        keys: KeyStore::from_env(),
+        password_hasher: init_password_hasher(),
+        presence: PresenceTracker::default(),
+    });
 
     let (layer, io) = SocketIo::builder()
         .with_state(shared_state.clone())
@@ -19,13 +37,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
     io.ns("/", on_connect);
 
     let app = Router::new()
+        .route("/.well-known/jwks.json", get(get_jwks))
         .nest("/auth", auth::routes(shared_state.clone()))
         .nest("/chat", chat::routes(shared_state.clone()))
         .nest("/user", user::routes(shared_state.clone()))
         .with_state(shared_state)
+        .layer(Extension(io))
         .layer(layer);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
     Ok(())
 }
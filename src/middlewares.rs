@@ -7,10 +7,7 @@ use axum::{
     response::Response,
 };
 
-use crate::{
-    auth::{jwt::decode_jwt_payload, registration::User},
-    AppState,
-};
+use crate::{auth::session::find_session_user, AppState};
 
 pub async fn jwt_authorization(
     State(state): State<Arc<AppState>>,
@@ -34,28 +31,12 @@ pub async fn jwt_authorization(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let jwt_payload = decode_jwt_payload(split_auth_header[1]);
-
-    let jwt_payload = match jwt_payload {
-        Ok(payload) => payload,
-        Err(_) => {
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    };
-
-    let user_search = sqlx::query_as!(
-        User,
-        "SELECT * FROM chat.user WHERE id=$1 LIMIT 1",
-        jwt_payload.id,
-    )
-    .fetch_one(&state.db_pool)
-    .await;
-
-    match user_search {
-        Ok(user) => {
+    match find_session_user(&state, split_auth_header[1]).await {
+        Some((user, session)) => {
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(session);
             Ok(next.run(req).await)
         }
-        Err(_) => Err(StatusCode::UNAUTHORIZED),
+        None => Err(StatusCode::UNAUTHORIZED),
     }
 }
@@ -1,29 +1,39 @@
 use std::sync::Arc;
 
 use axum::http::header::AUTHORIZATION;
-use member::{add_member, leave_chat, remove_member};
+use member::{
+    add_member, approve_join_request, leave_chat, reject_join_request, remove_member, transfer_chat,
+};
 use message::{delete_message, send_message, update_message};
+use presence::track_connect;
+use reaction::{add_reaction, remove_reaction};
+use receipt::mark_read;
 use serde::{Deserialize, Serialize};
-use socketioxide::extract::{SocketRef, State, TryData};
-use sqlx::{types::Uuid, Pool, Postgres};
+use socketioxide::{
+    extract::{SocketRef, State, TryData},
+    SocketIo,
+};
+use sqlx::types::Uuid;
+use typing::{stop_typing, typing_start, typing_stop};
 
 use crate::{
-    auth::{jwt::decode_jwt_payload, registration::User},
+    auth::{registration::User, session::find_session_user},
     AppState,
 };
 
 mod member;
 mod message;
+pub mod presence;
+mod reaction;
+mod receipt;
+mod typing;
 
 pub trait GetUser {
-    fn get_user(
-        &self,
-        executor: &Pool<Postgres>,
-    ) -> impl std::future::Future<Output = Option<User>>;
+    fn get_user(&self, state: &AppState) -> impl std::future::Future<Output = Option<User>>;
 }
 
 impl GetUser for SocketRef {
-    async fn get_user(&self, executor: &Pool<Postgres>) -> Option<User> {
+    async fn get_user(&self, state: &AppState) -> Option<User> {
         let auth_header = self
             .req_parts()
             .headers
@@ -40,23 +50,9 @@ impl GetUser for SocketRef {
             return None;
         }
 
-        let jwt_payload = decode_jwt_payload(split_header[1]);
-
-        let jwt_payload = match jwt_payload {
-            Ok(payload) => payload,
-            Err(_) => return None,
-        };
-
-        let user_search = sqlx::query_as!(
-            User,
-            "SELECT * FROM chat.user WHERE id = $1 LIMIT 1",
-            jwt_payload.id
-        )
-        .fetch_one(executor)
-        .await
-        .ok();
-
-        return user_search;
+        find_session_user(state, split_header[1])
+            .await
+            .map(|(user, _)| user)
     }
 }
 
@@ -65,19 +61,62 @@ mod socket_event {
     pub const ADD_USER: &'static str = "add-user";
     pub const REMOVE_USER: &'static str = "remove-user";
     pub const LEAVE_CHAT: &'static str = "leave-chat";
+    pub const TRANSFER_CHAT: &'static str = "transfer-chat";
+    pub const APPROVE_JOIN_REQUEST: &'static str = "approve-join-request";
+    pub const REJECT_JOIN_REQUEST: &'static str = "reject-join-request";
     pub const SEND_MESSAGE: &'static str = "send-message";
     pub const UPDATE_MESSAGE: &'static str = "update-message";
     pub const DELETE_MESSAGE: &'static str = "delete-message";
+    pub const ADD_REACTION: &'static str = "add-reaction";
+    pub const REMOVE_REACTION: &'static str = "remove-reaction";
+    pub const MARK_READ: &'static str = "mark-read";
+    pub const TYPING_START: &'static str = "typing-start";
+    pub const TYPING_STOP: &'static str = "typing-stop";
+}
+
+/// User who authenticated the socket when it connected, kept in the socket extensions.
+#[derive(Clone)]
+pub struct SocketUser {
+    pub id: Uuid,
+    pub username: String,
 }
 
-pub async fn on_connect(socket: SocketRef) {
+/// Every authenticated socket joins the room of its user,
+/// so that the server can reach all the sockets of a user at once.
+pub fn user_room(user_id: Uuid) -> String {
+    format!("user:{user_id}")
+}
+
+/// Disconnects all the live sockets of the user, e.g. after their tokens were invalidated.
+pub fn disconnect_user(io: &SocketIo, user_id: Uuid) {
+    io.within(user_room(user_id)).disconnect().ok();
+}
+
+pub async fn on_connect(socket: SocketRef, State(state): State<Arc<AppState>>) {
+    if let Some(user) = socket.get_user(&state).await {
+        socket.extensions.insert(SocketUser {
+            id: user.id,
+            username: user.username,
+        });
+        socket.join(user_room(user.id)).ok();
+        track_connect(socket.clone(), state.clone()).await;
+    }
+
     socket.on(socket_event::JOIN, join_chat_room);
     socket.on(socket_event::ADD_USER, add_member);
     socket.on(socket_event::REMOVE_USER, remove_member);
     socket.on(socket_event::LEAVE_CHAT, leave_chat);
+    socket.on(socket_event::TRANSFER_CHAT, transfer_chat);
+    socket.on(socket_event::APPROVE_JOIN_REQUEST, approve_join_request);
+    socket.on(socket_event::REJECT_JOIN_REQUEST, reject_join_request);
     socket.on(socket_event::SEND_MESSAGE, send_message);
     socket.on(socket_event::UPDATE_MESSAGE, update_message);
     socket.on(socket_event::DELETE_MESSAGE, delete_message);
+    socket.on(socket_event::ADD_REACTION, add_reaction);
+    socket.on(socket_event::REMOVE_REACTION, remove_reaction);
+    socket.on(socket_event::MARK_READ, mark_read);
+    socket.on(socket_event::TYPING_START, typing_start);
+    socket.on(socket_event::TYPING_STOP, typing_stop);
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -97,7 +136,7 @@ async fn join_chat_room(
             return;
         }
     };
-    let user = match socket.get_user(&state.db_pool).await {
+    let user = match socket.get_user(&state).await {
         Some(user) => user,
         None => {
             socket
@@ -121,8 +160,11 @@ async fn join_chat_room(
 
     match query_result {
         Ok(chat_id) => {
+            stop_typing(&socket);
             socket.leave_all().ok();
-            socket.join(chat_id.id.to_string()).ok();
+            socket
+                .join(vec![chat_id.id.to_string(), user_room(user.id)])
+                .ok();
             socket
                 .emit("success", "Successfully joined the chat room")
                 .ok();
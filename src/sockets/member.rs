@@ -4,7 +4,18 @@ use serde::Deserialize;
 use socketioxide::extract::{SocketRef, State, TryData};
 use uuid::Uuid;
 
-use crate::{sockets::GetUser, AppState};
+use crate::{
+    chat::{
+        channel::{JoinRequestResolved, JoinRequestStatus},
+        direct::is_direct_chat,
+        invitation::{create_invitation, invitations_required},
+        invite::MemberJoined,
+        ownership::transfer_ownership,
+        permission::{ChatPermission, ChatRole},
+    },
+    sockets::{user_room, GetUser},
+    AppState,
+};
 
 #[derive(Deserialize)]
 pub struct ChatMembershipInput {
@@ -24,7 +35,7 @@ pub async fn add_member(
             return;
         }
     };
-    let user = match socket.get_user(&state.db_pool).await {
+    let user = match socket.get_user(&state).await {
         Some(user) if user.id != data.user_id => user,
         Some(_) => {
             socket
@@ -40,33 +51,23 @@ pub async fn add_member(
         }
     };
 
-    let result = sqlx::query!(
-        "SELECT EXISTS (SELECT 1 FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2)",
-        user.id,
-        data.chat_id
-    )
-    .fetch_one(&state.db_pool)
-    .await;
-
-    match result {
-        Ok(val) => match val.exists {
-            Some(in_chat) if in_chat => {}
-            Some(_) => {
-                socket
-                    .emit(
-                        "error",
-                        "You cannot add users to chat you yourself are not the part of",
-                    )
-                    .ok();
-                return;
-            }
-            None => {
-                socket
-                    .emit("error", "Failed to check if you are in the chat")
-                    .ok();
-                return;
-            }
-        },
+    match user.chat_role(&state.db_pool, data.chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::AddMember) => {}
+        Ok(Some(_)) => {
+            socket
+                .emit("error", "You are not allowed to add users to this chat")
+                .ok();
+            return;
+        }
+        Ok(None) => {
+            socket
+                .emit(
+                    "error",
+                    "You cannot add users to chat you yourself are not the part of",
+                )
+                .ok();
+            return;
+        }
         Err(_) => {
             socket
                 .emit("error", "Failed to check if you are in the chat")
@@ -75,6 +76,36 @@ pub async fn add_member(
         }
     };
 
+    match is_direct_chat(&state.db_pool, data.chat_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            socket
+                .emit("error", "Users cannot be added to a direct chat")
+                .ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not check the settings of the chat")
+                .ok();
+            return;
+        }
+    }
+
+    match invitations_required(&state.db_pool, data.chat_id).await {
+        Ok(true) => {
+            invite_member(&socket, &state, user.id, data).await;
+            return;
+        }
+        Ok(false) => {}
+        Err(_) => {
+            socket
+                .emit("error", "Could not check the settings of the chat")
+                .ok();
+            return;
+        }
+    }
+
     let add_user = sqlx::query!(
         "INSERT INTO chat.user_chat (user_id, chat_id) VALUES($1, $2)",
         data.user_id,
@@ -120,6 +151,72 @@ pub async fn add_member(
     }
 }
 
+/// Sends an invitation to the user instead of adding them to the chat right away.
+async fn invite_member(
+    socket: &SocketRef,
+    state: &AppState,
+    inviter_id: Uuid,
+    data: ChatMembershipInput,
+) {
+    let is_member = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2) AS "exists!""#,
+        data.user_id,
+        data.chat_id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match is_member {
+        Ok(false) => {}
+        Ok(true) => {
+            socket.emit("error", "User is already in the chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not invite user due to internal reasons")
+                .ok();
+            return;
+        }
+    }
+
+    match create_invitation(&state.db_pool, data.chat_id, inviter_id, data.user_id).await {
+        Ok(invitation) => {
+            socket
+                .within(user_room(data.user_id))
+                .emit("invitation", invitation)
+                .ok();
+            socket
+                .emit("success", "Successfully invited the user to the chat")
+                .ok();
+        }
+        Err(e) => match e {
+            sqlx::Error::Database(e) => match e.kind() {
+                sqlx::error::ErrorKind::UniqueViolation => {
+                    socket
+                        .emit("error", "User has already been invited to the chat")
+                        .ok();
+                }
+                sqlx::error::ErrorKind::ForeignKeyViolation => {
+                    socket
+                        .emit("error", "User with such an id does not exist")
+                        .ok();
+                }
+                _ => {
+                    socket
+                        .emit("error", "Could not invite user due to internal reasons")
+                        .ok();
+                }
+            },
+            _ => {
+                socket
+                    .emit("error", "Could not invite user due to internal reasons")
+                    .ok();
+            }
+        },
+    }
+}
+
 pub async fn remove_member(
     socket: SocketRef,
     TryData(data): TryData<ChatMembershipInput>,
@@ -132,7 +229,7 @@ pub async fn remove_member(
             return;
         }
     };
-    let user = socket.get_user(&state.db_pool).await;
+    let user = socket.get_user(&state).await;
     let user = match user {
         Some(user) if user.id != data.user_id => user,
         Some(_) => {
@@ -149,11 +246,11 @@ pub async fn remove_member(
         }
     };
 
-    match user.is_admin(&state.db_pool, data.chat_id).await {
-        Ok(is_admin) if is_admin => {}
+    let role = match user.chat_role(&state.db_pool, data.chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::RemoveMember) => role,
         Ok(_) => {
             socket
-                .emit("error", "Only admin can remove other users from the chat")
+                .emit("error", "Only admins can remove other users from the chat")
                 .ok();
             return;
         }
@@ -166,12 +263,14 @@ pub async fn remove_member(
                 .ok();
             return;
         }
-    }
+    };
 
+    // Only the members with a lower role can be removed
     let deletion_result = sqlx::query!(
-        "DELETE FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2 RETURNING user_id",
+        "DELETE FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2 AND role < $3 RETURNING user_id",
         data.user_id,
-        data.chat_id
+        data.chat_id,
+        role as ChatRole
     )
     .fetch_one(&state.db_pool)
     .await;
@@ -185,7 +284,12 @@ pub async fn remove_member(
         }
         Err(e) => match e {
             sqlx::Error::RowNotFound => {
-                socket.emit("error", "Could not find user in chat").ok();
+                socket
+                    .emit(
+                        "error",
+                        "Could not find user with a role lower than yours in chat",
+                    )
+                    .ok();
                 return;
             }
             _ => {
@@ -213,7 +317,7 @@ pub async fn leave_chat(
             return;
         }
     };
-    let user = socket.get_user(&state.db_pool).await;
+    let user = socket.get_user(&state).await;
     let user = match user {
         Some(user) => user,
         None => {
@@ -224,26 +328,42 @@ pub async fn leave_chat(
         }
     };
 
-    match user.is_admin(&state.db_pool, data.chat_id).await {
-        Ok(is_admin) if !is_admin => {}
-        Ok(_) => {
+    match user.chat_role(&state.db_pool, data.chat_id).await {
+        Ok(Some(role)) if role != ChatRole::Owner => {}
+        Ok(Some(_)) => {
             socket
-                .emit("error", "Admin cannot leave their own chat")
+                .emit(
+                    "error",
+                    "Owner cannot leave their own chat. Transfer it to another member first",
+                )
+                .ok();
+            return;
+        }
+        Ok(None) => {
+            socket.emit("error", "Could not find you in this chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not check if you are an owner of the chat")
+                .ok();
+            return;
+        }
+    }
+
+    // Direct chats can't be joined again once left, so their parties have to stay
+    match is_direct_chat(&state.db_pool, data.chat_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            socket.emit("error", "You cannot leave a direct chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not check the settings of the chat")
                 .ok();
             return;
         }
-        Err(e) => match e {
-            sqlx::Error::RowNotFound => {
-                socket.emit("error", "Could not find you in this chat").ok();
-                return;
-            }
-            _ => {
-                socket
-                    .emit("error", "Could not check if you are an admin of the chat")
-                    .ok();
-                return;
-            }
-        },
     }
 
     let deletion_result = sqlx::query!(
@@ -270,3 +390,280 @@ pub async fn leave_chat(
         },
     }
 }
+
+pub async fn transfer_chat(
+    socket: SocketRef,
+    TryData(data): TryData<ChatMembershipInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    let data = match data {
+        Ok(data) => data,
+        Err(_) => {
+            socket.emit("error", "Could not parse body. Please, make sure you have all the required fields with correct names").ok();
+            return;
+        }
+    };
+    let user = match socket.get_user(&state).await {
+        Some(user) if user.id != data.user_id => user,
+        Some(_) => {
+            socket.emit("error", "You already own this chat").ok();
+            return;
+        }
+        None => {
+            socket
+                .emit("error", "Could not authenticate the user by auth header")
+                .ok();
+            return;
+        }
+    };
+
+    match user.chat_role(&state.db_pool, data.chat_id).await {
+        Ok(Some(ChatRole::Owner)) => {}
+        Ok(Some(_)) => {
+            socket
+                .emit("error", "Only owner can transfer the ownership of the chat")
+                .ok();
+            return;
+        }
+        Ok(None) => {
+            socket.emit("error", "Could not find you in this chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not check if you are an owner of the chat")
+                .ok();
+            return;
+        }
+    }
+
+    match transfer_ownership(&state.db_pool, data.chat_id, user.id, data.user_id).await {
+        Ok(transferred) => {
+            socket
+                .to(data.chat_id.to_string())
+                .emit("ownership-transferred", transferred)
+                .ok();
+            socket
+                .emit("success", "Successfully transferred the chat")
+                .ok();
+        }
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                socket
+                    .emit("error", "New owner has to be a member of the chat")
+                    .ok();
+            }
+            _ => {
+                socket.emit("error", "Could not transfer the chat").ok();
+            }
+        },
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JoinRequestInput {
+    request_id: Uuid,
+}
+
+/// Approves the join request if `approved` is set, and rejects it otherwise.
+async fn resolve_join_request(
+    socket: &SocketRef,
+    state: &AppState,
+    data: Result<JoinRequestInput, serde_json::Error>,
+    approved: bool,
+) {
+    let data = match data {
+        Ok(data) => data,
+        Err(_) => {
+            socket.emit("error", "Could not parse body. Please, make sure you have all the required fields with correct names").ok();
+            return;
+        }
+    };
+    let user = match socket.get_user(state).await {
+        Some(user) => user,
+        None => {
+            socket
+                .emit("error", "Could not authenticate the user by auth header")
+                .ok();
+            return;
+        }
+    };
+
+    struct PendingRequest {
+        chat_id: Uuid,
+        user_id: Uuid,
+        username: String,
+    }
+    let request_result = sqlx::query_as!(
+        PendingRequest,
+        "
+        SELECT jr.chat_id, jr.user_id, u.username
+        FROM chat.join_request AS jr
+        INNER JOIN chat.user AS u
+        ON u.id = jr.user_id
+        WHERE jr.id = $1 AND jr.status = 'pending'
+        ",
+        data.request_id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let request = match request_result {
+        Ok(request) => request,
+        Err(sqlx::Error::RowNotFound) => {
+            socket
+                .emit(
+                    "error",
+                    "Could not find pending join request with such an id",
+                )
+                .ok();
+            return;
+        }
+        Err(_) => {
+            socket.emit("error", "Could not find the join request").ok();
+            return;
+        }
+    };
+
+    match user
+        .has_permission(
+            &state.db_pool,
+            request.chat_id,
+            ChatPermission::ManageJoinRequests,
+        )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            socket
+                .emit("error", "Only admins can respond to join requests")
+                .ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit(
+                    "error",
+                    "Could not validate that you are an admin of the chat",
+                )
+                .ok();
+            return;
+        }
+    }
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            socket
+                .emit("error", "Could not respond to the join request")
+                .ok();
+            return;
+        }
+    };
+
+    let status = if approved {
+        JoinRequestStatus::Approved
+    } else {
+        JoinRequestStatus::Rejected
+    };
+    let update_result = sqlx::query!(
+        "
+        UPDATE chat.join_request SET status = $1, responded_at = NOW()::timestamp, responded_by = $2
+        WHERE id = $3 AND status = 'pending'
+        RETURNING id
+        ",
+        status as JoinRequestStatus,
+        user.id,
+        data.request_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    match update_result {
+        Ok(_) => {}
+        Err(sqlx::Error::RowNotFound) => {
+            socket
+                .emit("error", "The join request has already been responded to")
+                .ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not respond to the join request")
+                .ok();
+            return;
+        }
+    }
+
+    if approved {
+        let insert_result = sqlx::query!(
+            "INSERT INTO chat.user_chat (user_id, chat_id, role) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            request.user_id,
+            request.chat_id,
+            ChatRole::Member as ChatRole
+        )
+        .execute(&mut *tx)
+        .await;
+
+        if insert_result.is_err() {
+            socket
+                .emit(
+                    "error",
+                    "Could not add user to the chat due to internal reasons",
+                )
+                .ok();
+            return;
+        }
+    }
+
+    if tx.commit().await.is_err() {
+        socket
+            .emit("error", "Could not respond to the join request")
+            .ok();
+        return;
+    }
+
+    if approved {
+        socket
+            .within(request.chat_id.to_string())
+            .emit(
+                "member-joined",
+                MemberJoined {
+                    chat_id: request.chat_id,
+                    user_id: request.user_id,
+                    username: request.username,
+                },
+            )
+            .ok();
+    }
+    socket
+        .within(user_room(request.user_id))
+        .emit(
+            "join-request-resolved",
+            JoinRequestResolved {
+                request_id: data.request_id,
+                chat_id: request.chat_id,
+                approved,
+            },
+        )
+        .ok();
+    socket
+        .emit("success", "Successfully responded to the join request")
+        .ok();
+}
+
+pub async fn approve_join_request(
+    socket: SocketRef,
+    TryData(data): TryData<JoinRequestInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    resolve_join_request(&socket, &state, data, true).await;
+}
+
+pub async fn reject_join_request(
+    socket: SocketRef,
+    TryData(data): TryData<JoinRequestInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    resolve_join_request(&socket, &state, data, false).await;
+}
@@ -3,22 +3,65 @@ use std::sync::Arc;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use socketioxide::extract::{SocketRef, State, TryData};
-use sqlx::types::Uuid;
+use sqlx::{types::Uuid, Pool, Postgres};
 
-use crate::{sockets::GetUser, AppState};
+use crate::{
+    chat::{
+        message::{find_preview, find_thread_summary, MessagePreview},
+        permission::ChatPermission,
+        reaction::ReactionCount,
+    },
+    sockets::GetUser,
+    AppState,
+};
 
 #[derive(Deserialize)]
 pub struct SendMessageInput {
     content: String,
     chat_id: Uuid,
+    /// Message of the same chat which is replied to.
+    reply_to_id: Option<Uuid>,
 }
 
 #[derive(Serialize)]
 struct NormalizedMessage {
     id: Uuid,
     content: String,
-    user_id: Uuid,
-    created_at: Option<NaiveDateTime>,
+    /// `None` if the author deleted their account.
+    user_id: Option<Uuid>,
+    created_at: NaiveDateTime,
+    reply_to_id: Option<Uuid>,
+    /// `None` unless the message is a reply.
+    thread_root_id: Option<Uuid>,
+    /// `None` if the message isn't a reply or the quoted message was deleted.
+    reply_to: Option<MessagePreview>,
+    /// Left out of updates, which don't change the reactions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reactions: Option<Vec<ReactionCount>>,
+}
+
+/// Sent to the chat room as `thread-deleted` once the root of a thread is deleted.
+#[derive(Serialize)]
+struct ThreadDeleted {
+    chat_id: Uuid,
+    thread_root_id: Uuid,
+    /// Replies of the thread, which are top-level messages from now on.
+    reply_ids: Vec<Uuid>,
+}
+
+/// Lets the chat room know that the number of replies in the thread has changed.
+async fn emit_thread_update(
+    socket: &SocketRef,
+    executor: &Pool<Postgres>,
+    chat_id: Uuid,
+    thread_root_id: Uuid,
+) {
+    if let Ok(summary) = find_thread_summary(executor, chat_id, thread_root_id).await {
+        socket
+            .within(chat_id.to_string())
+            .emit("thread-updated", summary)
+            .ok();
+    }
 }
 
 pub async fn send_message(
@@ -39,7 +82,7 @@ pub async fn send_message(
             .ok();
         return;
     }
-    let user = socket.get_user(&state.db_pool).await;
+    let user = socket.get_user(&state).await;
     let user = match user {
         Some(user) => user,
         None => {
@@ -50,33 +93,23 @@ pub async fn send_message(
         }
     };
 
-    let result = sqlx::query!(
-        "SELECT EXISTS (SELECT 1 FROM chat.user_chat WHERE user_id = $1 AND chat_id = $2)",
-        user.id,
-        data.chat_id
-    )
-    .fetch_one(&state.db_pool)
-    .await;
-
-    match result {
-        Ok(val) => match val.exists {
-            Some(in_chat) if in_chat => {}
-            Some(_) => {
-                socket
-                    .emit(
-                        "error",
-                        "You send messages to the chat you yourself are not the part of",
-                    )
-                    .ok();
-                return;
-            }
-            None => {
-                socket
-                    .emit("error", "Failed to check if you are in the chat")
-                    .ok();
-                return;
-            }
-        },
+    match user.chat_role(&state.db_pool, data.chat_id).await {
+        Ok(Some(role)) if role.can(ChatPermission::SendMessage) => {}
+        Ok(Some(_)) => {
+            socket
+                .emit("error", "You are not allowed to send messages to this chat")
+                .ok();
+            return;
+        }
+        Ok(None) => {
+            socket
+                .emit(
+                    "error",
+                    "You send messages to the chat you yourself are not the part of",
+                )
+                .ok();
+            return;
+        }
         Err(_) => {
             socket
                 .emit("error", "Failed to check if you are in the chat")
@@ -85,22 +118,87 @@ pub async fn send_message(
         }
     };
 
+    // Replies join the thread of the message they reply to
+    let thread_root_id = match data.reply_to_id {
+        Some(reply_to_id) => {
+            let root_result = sqlx::query_scalar!(
+                r#"SELECT COALESCE(thread_root_id, id) AS "thread_root_id!" FROM chat.message WHERE id = $1 AND chat_id = $2"#,
+                reply_to_id,
+                data.chat_id
+            )
+            .fetch_one(&state.db_pool)
+            .await;
+
+            match root_result {
+                Ok(thread_root_id) => Some(thread_root_id),
+                Err(sqlx::Error::RowNotFound) => {
+                    socket
+                        .emit(
+                            "error",
+                            "Could not find the message to reply to in this chat",
+                        )
+                        .ok();
+                    return;
+                }
+                Err(_) => {
+                    socket.emit("error", "Could not send a message").ok();
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    struct CreatedMessage {
+        id: Uuid,
+        content: String,
+        user_id: Option<Uuid>,
+        created_at: NaiveDateTime,
+    }
     let create_message = sqlx::query_as!(
-        NormalizedMessage,
-        "INSERT INTO chat.message (content, user_id, chat_id) VALUES ($1, $2, $3) RETURNING id, content, user_id, created_at",
+        CreatedMessage,
+        "
+        INSERT INTO chat.message (content, user_id, chat_id, reply_to_id, thread_root_id)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, content, user_id, created_at
+        ",
         data.content.trim(),
         user.id,
-        data.chat_id
+        data.chat_id,
+        data.reply_to_id,
+        thread_root_id
     )
     .fetch_one(&state.db_pool)
     .await;
 
     match create_message {
         Ok(message) => {
+            let reply_to = match data.reply_to_id {
+                Some(reply_to_id) => find_preview(&state.db_pool, reply_to_id)
+                    .await
+                    .ok()
+                    .flatten(),
+                None => None,
+            };
             socket
                 .within(data.chat_id.to_string())
-                .emit("new-message", message)
+                .emit(
+                    "new-message",
+                    NormalizedMessage {
+                        id: message.id,
+                        content: message.content,
+                        user_id: message.user_id,
+                        created_at: message.created_at,
+                        reply_to_id: data.reply_to_id,
+                        thread_root_id,
+                        reply_to,
+                        reactions: Some(Vec::new()),
+                    },
+                )
                 .ok();
+            if let Some(thread_root_id) = thread_root_id {
+                emit_thread_update(&socket, &state.db_pool, data.chat_id, thread_root_id).await;
+            }
         }
         Err(_) => {
             socket.emit("error", "Could not send a message").ok();
@@ -108,6 +206,25 @@ pub async fn send_message(
     }
 }
 
+pub struct MessageOrigin {
+    pub user_id: Option<Uuid>,
+    pub chat_id: Uuid,
+}
+
+/// Finds the author and the chat of the message to check what the user may do with it.
+pub async fn find_message_origin(
+    executor: &Pool<Postgres>,
+    message_id: Uuid,
+) -> sqlx::Result<MessageOrigin> {
+    sqlx::query_as!(
+        MessageOrigin,
+        "SELECT user_id, chat_id FROM chat.message WHERE id = $1",
+        message_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
 #[derive(Deserialize)]
 pub struct UpdateMessageInput {
     new_content: String,
@@ -132,7 +249,7 @@ pub async fn update_message(
         return;
     }
 
-    let user = socket.get_user(&state.db_pool).await;
+    let user = socket.get_user(&state).await;
     let user = match user {
         Some(user) => user,
         None => {
@@ -143,16 +260,57 @@ pub async fn update_message(
         }
     };
 
+    match find_message_origin(&state.db_pool, data.message_id).await {
+        Ok(origin) if origin.user_id == Some(user.id) => {
+            match user
+                .has_permission(&state.db_pool, origin.chat_id, ChatPermission::SendMessage)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    socket
+                        .emit("error", "You are not allowed to send messages to this chat")
+                        .ok();
+                    return;
+                }
+                Err(_) => {
+                    socket
+                        .emit("error", "Failed to check if you are in the chat")
+                        .ok();
+                    return;
+                }
+            }
+        }
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            socket
+                .emit(
+                    "error",
+                    "The message doesn't exist or you are trying to update someone else's message",
+                )
+                .ok();
+            return;
+        }
+        Err(_) => {
+            socket.emit("error", "Could not update the message").ok();
+            return;
+        }
+    }
+
     struct UpdatedMessage {
         id: Uuid,
         content: String,
-        user_id: Uuid,
-        created_at: Option<NaiveDateTime>,
+        user_id: Option<Uuid>,
+        created_at: NaiveDateTime,
         chat_id: Uuid,
+        reply_to_id: Option<Uuid>,
+        thread_root_id: Option<Uuid>,
     }
     let update_result = sqlx::query_as!(
         UpdatedMessage,
-        "UPDATE chat.message SET content = $1 WHERE id = $2 AND user_id = $3 RETURNING id, content, user_id, created_at, chat_id",
+        "
+        UPDATE chat.message SET content = $1 WHERE id = $2 AND user_id = $3
+        RETURNING id, content, user_id, created_at, chat_id, reply_to_id, thread_root_id
+        ",
         data.new_content,
         data.message_id,
         user.id
@@ -162,6 +320,13 @@ pub async fn update_message(
 
     match update_result {
         Ok(message) => {
+            let reply_to = match message.reply_to_id {
+                Some(reply_to_id) => find_preview(&state.db_pool, reply_to_id)
+                    .await
+                    .ok()
+                    .flatten(),
+                None => None,
+            };
             socket
                 .within(message.chat_id.to_string())
                 .emit(
@@ -171,6 +336,10 @@ pub async fn update_message(
                         user_id: message.user_id,
                         created_at: message.created_at,
                         content: message.content,
+                        reply_to_id: message.reply_to_id,
+                        thread_root_id: message.thread_root_id,
+                        reply_to,
+                        reactions: None,
                     },
                 )
                 .ok();
@@ -202,7 +371,7 @@ pub async fn delete_message(
             return;
         }
     };
-    let user = socket.get_user(&state.db_pool).await;
+    let user = socket.get_user(&state).await;
     let user = match user {
         Some(user) => user,
         None => {
@@ -213,28 +382,122 @@ pub async fn delete_message(
         }
     };
 
-    let deletion_result = sqlx::query!(
-        "DELETE FROM chat.message WHERE id = $1 AND user_id = $2 RETURNING chat_id",
-        data.message_id,
-        user.id
+    // Authors can delete their own messages, and moderators can delete anyone's
+    match find_message_origin(&state.db_pool, data.message_id).await {
+        Ok(origin) if origin.user_id == Some(user.id) => {}
+        Ok(origin) => {
+            match user
+                .has_permission(
+                    &state.db_pool,
+                    origin.chat_id,
+                    ChatPermission::DeleteAnyMessage,
+                )
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    socket.emit("error", "Could not find the message to delete or you are not allowed to delete someone else's message").ok();
+                    return;
+                }
+                Err(_) => {
+                    socket.emit("error", "Could not delete the message").ok();
+                    return;
+                }
+            }
+        }
+        Err(sqlx::Error::RowNotFound) => {
+            socket.emit("error", "Could not find the message to delete or you are not allowed to delete someone else's message").ok();
+            return;
+        }
+        Err(_) => {
+            socket.emit("error", "Could not delete the message").ok();
+            return;
+        }
+    }
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            socket.emit("error", "Could not delete the message").ok();
+            return;
+        }
+    };
+
+    // Locked first, so that no reply can be added to the thread while its replies are collected
+    let lock_result = sqlx::query!(
+        "SELECT id FROM chat.message WHERE id = $1 FOR UPDATE",
+        data.message_id
     )
-    .fetch_one(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await;
-
-    match deletion_result {
-        Ok(val) => {
+    match lock_result {
+        Ok(_) => {}
+        Err(sqlx::Error::RowNotFound) => {
             socket
-                .within(val.chat_id.to_string())
-                .emit("deleted-message", data)
+                .emit("error", "Could not find the message to delete")
                 .ok();
+            return;
         }
-        Err(e) => match e {
-            sqlx::Error::RowNotFound => {
-                socket.emit("error", "Could not find the message to delete or you are trying to delete someone else's message").ok();
-            }
-            _ => {
-                socket.emit("error", "Could not delete the message").ok();
-            }
-        },
+        Err(_) => {
+            socket.emit("error", "Could not delete the message").ok();
+            return;
+        }
+    }
+
+    // Deleting the root detaches its replies, see the `ON DELETE SET NULL` of `thread_root_id`
+    let reply_ids = sqlx::query_scalar!(
+        "SELECT id FROM chat.message WHERE thread_root_id = $1",
+        data.message_id
+    )
+    .fetch_all(&mut *tx)
+    .await;
+    let reply_ids = match reply_ids {
+        Ok(reply_ids) => reply_ids,
+        Err(_) => {
+            socket.emit("error", "Could not delete the message").ok();
+            return;
+        }
+    };
+
+    let deletion_result = sqlx::query!(
+        "DELETE FROM chat.message WHERE id = $1 RETURNING chat_id, thread_root_id",
+        data.message_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let deleted = match deletion_result {
+        Ok(deleted) => deleted,
+        Err(_) => {
+            socket.emit("error", "Could not delete the message").ok();
+            return;
+        }
+    };
+
+    if tx.commit().await.is_err() {
+        socket.emit("error", "Could not delete the message").ok();
+        return;
+    }
+
+    let deleted_id = data.message_id;
+    socket
+        .within(deleted.chat_id.to_string())
+        .emit("deleted-message", data)
+        .ok();
+    if let Some(thread_root_id) = deleted.thread_root_id {
+        emit_thread_update(&socket, &state.db_pool, deleted.chat_id, thread_root_id).await;
+    }
+    if !reply_ids.is_empty() {
+        socket
+            .within(deleted.chat_id.to_string())
+            .emit(
+                "thread-deleted",
+                ThreadDeleted {
+                    chat_id: deleted.chat_id,
+                    thread_root_id: deleted_id,
+                    reply_ids,
+                },
+            )
+            .ok();
     }
 }
@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use socketioxide::{extract::SocketRef, socket::Sid};
+use sqlx::{types::Uuid, Pool, Postgres};
+
+use crate::{
+    sockets::{user_room, SocketUser},
+    AppState,
+};
+
+/// Status which the user has chosen for themselves, kept while they are offline too.
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[sqlx(type_name = "chat.presence_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Available,
+    Away,
+    DoNotDisturb,
+}
+
+/// What other users see of the user.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    Online,
+    Away,
+    DoNotDisturb,
+    Offline,
+}
+
+impl Presence {
+    pub fn new(online: bool, status: PresenceStatus) -> Presence {
+        match (online, status) {
+            (false, _) => Presence::Offline,
+            (true, PresenceStatus::Available) => Presence::Online,
+            (true, PresenceStatus::Away) => Presence::Away,
+            (true, PresenceStatus::DoNotDisturb) => Presence::DoNotDisturb,
+        }
+    }
+}
+
+/// Connected sockets of each user, so that a user with several tabs stays online until the last one is closed.
+#[derive(Default)]
+pub struct PresenceTracker {
+    sockets: Mutex<HashMap<Uuid, HashSet<Sid>>>,
+}
+
+impl PresenceTracker {
+    /// Returns whether it is the first socket of the user.
+    pub fn connect(&self, user_id: Uuid, sid: Sid) -> bool {
+        let mut sockets = self.sockets.lock().unwrap();
+        let user_sockets = sockets.entry(user_id).or_default();
+        user_sockets.insert(sid);
+        user_sockets.len() == 1
+    }
+
+    /// Returns whether it was the last socket of the user.
+    pub fn disconnect(&self, user_id: Uuid, sid: Sid) -> bool {
+        let mut sockets = self.sockets.lock().unwrap();
+        match sockets.get_mut(&user_id) {
+            Some(user_sockets) => {
+                user_sockets.remove(&sid);
+                if user_sockets.is_empty() {
+                    sockets.remove(&user_id);
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_online(&self, user_id: Uuid) -> bool {
+        self.sockets.lock().unwrap().contains_key(&user_id)
+    }
+}
+
+/// Sent as the `presence` event to the user and everyone who shares a chat with them.
+#[derive(Serialize)]
+pub struct PresenceUpdate {
+    pub user_id: Uuid,
+    pub presence: Presence,
+    /// `None` until the user disconnects for the first time.
+    pub last_seen_at: Option<NaiveDateTime>,
+}
+
+/// Rooms of the user and of everyone who shares a chat with them.
+pub async fn presence_rooms(executor: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<Vec<String>> {
+    let user_ids = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT other.user_id AS "user_id!"
+        FROM chat.user_chat AS mine
+        INNER JOIN chat.user_chat AS other
+        ON other.chat_id = mine.chat_id
+        WHERE mine.user_id = $1
+        UNION
+        SELECT $1
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(user_ids.into_iter().map(user_room).collect())
+}
+
+async fn broadcast_presence(socket: &SocketRef, state: &AppState, user_id: Uuid) {
+    struct StoredPresence {
+        presence_status: PresenceStatus,
+        last_seen_at: Option<NaiveDateTime>,
+    }
+    let stored = sqlx::query_as!(
+        StoredPresence,
+        r#"SELECT presence_status AS "presence_status: PresenceStatus", last_seen_at FROM chat.user WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let (stored, rooms) = match (stored, presence_rooms(&state.db_pool, user_id).await) {
+        (Ok(stored), Ok(rooms)) => (stored, rooms),
+        _ => return,
+    };
+
+    socket
+        .within(rooms)
+        .emit(
+            "presence",
+            PresenceUpdate {
+                user_id,
+                presence: Presence::new(state.presence.is_online(user_id), stored.presence_status),
+                last_seen_at: stored.last_seen_at,
+            },
+        )
+        .ok();
+}
+
+/// Starts tracking the authenticated socket, and lets others know if the user has just come online.
+pub async fn track_connect(socket: SocketRef, state: Arc<AppState>) {
+    let user = match socket.extensions.get::<SocketUser>() {
+        Some(user) => user,
+        None => return,
+    };
+
+    // Registered before anything is awaited, so that a socket dropped in the meantime is still untracked
+    let disconnect_state = state.clone();
+    socket.on_disconnect(move |socket: SocketRef| track_disconnect(socket, disconnect_state));
+    let first_socket = state.presence.connect(user.id, socket.id);
+
+    // The socket may have dropped even before the handler was registered
+    if !socket.connected() {
+        track_disconnect(socket, state).await;
+        return;
+    }
+    if first_socket {
+        broadcast_presence(&socket, &state, user.id).await;
+    }
+}
+
+async fn track_disconnect(socket: SocketRef, state: Arc<AppState>) {
+    let user = match socket.extensions.get::<SocketUser>() {
+        Some(user) => user,
+        None => return,
+    };
+
+    if state.presence.disconnect(user.id, socket.id) {
+        sqlx::query!(
+            "UPDATE chat.user SET last_seen_at = NOW()::timestamp WHERE id = $1",
+            user.id
+        )
+        .execute(&state.db_pool)
+        .await
+        .ok();
+        broadcast_presence(&socket, &state, user.id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_users_hide_their_status() {
+        for status in [
+            PresenceStatus::Available,
+            PresenceStatus::Away,
+            PresenceStatus::DoNotDisturb,
+        ] {
+            assert_eq!(Presence::new(false, status), Presence::Offline);
+        }
+        assert_eq!(
+            Presence::new(true, PresenceStatus::Available),
+            Presence::Online
+        );
+        assert_eq!(Presence::new(true, PresenceStatus::Away), Presence::Away);
+        assert_eq!(
+            Presence::new(true, PresenceStatus::DoNotDisturb),
+            Presence::DoNotDisturb
+        );
+    }
+
+    #[test]
+    fn user_stays_online_until_last_socket() {
+        let tracker = PresenceTracker::default();
+        let user_id = Uuid::nil();
+        let (first, second) = (Sid::new(), Sid::new());
+
+        assert!(tracker.connect(user_id, first));
+        assert!(!tracker.connect(user_id, second));
+        assert!(tracker.is_online(user_id));
+
+        assert!(!tracker.disconnect(user_id, first));
+        assert!(tracker.is_online(user_id));
+        assert!(tracker.disconnect(user_id, second));
+        assert!(!tracker.is_online(user_id));
+    }
+
+    #[test]
+    fn repeated_disconnect_is_ignored() {
+        let tracker = PresenceTracker::default();
+        let user_id = Uuid::nil();
+        let sid = Sid::new();
+
+        tracker.connect(user_id, sid);
+        assert!(tracker.disconnect(user_id, sid));
+        assert!(!tracker.disconnect(user_id, sid));
+        assert!(!tracker.is_online(user_id));
+    }
+}
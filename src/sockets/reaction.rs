@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use socketioxide::extract::{SocketRef, State, TryData};
+use sqlx::types::Uuid;
+
+use crate::{
+    chat::{
+        permission::ChatPermission,
+        reaction::{find_reactors, is_single_emoji, ReactionCount},
+    },
+    sockets::{message::find_message_origin, GetUser, SocketUser},
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct ReactionInput {
+    message_id: Uuid,
+    emoji: String,
+}
+
+#[derive(Serialize)]
+struct ReactionsUpdated {
+    chat_id: Uuid,
+    message_id: Uuid,
+    reactions: Vec<ReactionCount>,
+}
+
+/// Sends the new reaction counts to every socket in the chat room,
+/// each with its own flag of whether its user has reacted.
+async fn broadcast_reactions(
+    socket: &SocketRef,
+    state: &AppState,
+    chat_id: Uuid,
+    message_id: Uuid,
+) {
+    let reactors = match find_reactors(&state.db_pool, message_id).await {
+        Ok(reactors) => reactors,
+        Err(_) => return,
+    };
+    let sockets = match socket.within(chat_id.to_string()).sockets() {
+        Ok(sockets) => sockets,
+        Err(_) => return,
+    };
+
+    for room_socket in sockets {
+        let viewer_id = room_socket
+            .extensions
+            .get::<SocketUser>()
+            .map(|user| user.id);
+        let update = ReactionsUpdated {
+            chat_id,
+            message_id,
+            reactions: reactors
+                .iter()
+                .map(|reactors| reactors.count_for(viewer_id))
+                .collect(),
+        };
+        room_socket.emit("reactions-updated", update).ok();
+    }
+}
+
+/// Adds the reaction if `add` is set, and removes it otherwise.
+async fn change_reaction(
+    socket: &SocketRef,
+    state: &AppState,
+    data: Result<ReactionInput, serde_json::Error>,
+    add: bool,
+) {
+    let data = match data {
+        Ok(data) => data,
+        Err(_) => {
+            socket.emit("error", "Could not parse body. Please, make sure you have all the required fields with correct names").ok();
+            return;
+        }
+    };
+    let emoji = data.emoji.trim();
+    if !is_single_emoji(emoji) {
+        socket
+            .emit("error", "Reaction should be a single emoji")
+            .ok();
+        return;
+    }
+
+    let user = match socket.get_user(state).await {
+        Some(user) => user,
+        None => {
+            socket
+                .emit("error", "Could not authenticate the user by auth header")
+                .ok();
+            return;
+        }
+    };
+
+    let chat_id = match find_message_origin(&state.db_pool, data.message_id).await {
+        Ok(origin) => origin.chat_id,
+        Err(sqlx::Error::RowNotFound) => {
+            socket.emit("error", "Could not find the message").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit(
+                    "error",
+                    "Could not find the message due to internal reasons",
+                )
+                .ok();
+            return;
+        }
+    };
+
+    // Any member can take their reaction back, even after losing the permission to react
+    let allowed = if add {
+        user.has_permission(&state.db_pool, chat_id, ChatPermission::React)
+            .await
+    } else {
+        user.is_member(&state.db_pool, chat_id).await
+    };
+    match allowed {
+        Ok(true) => {}
+        Ok(false) if add => {
+            socket
+                .emit(
+                    "error",
+                    "You are not allowed to react to messages in this chat",
+                )
+                .ok();
+            return;
+        }
+        Ok(false) => {
+            socket.emit("error", "Could not find you in this chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Failed to check if you are in the chat")
+                .ok();
+            return;
+        }
+    }
+
+    let change_result = if add {
+        sqlx::query!(
+            "
+            INSERT INTO chat.reaction (message_id, user_id, emoji) VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            RETURNING message_id
+            ",
+            data.message_id,
+            user.id,
+            emoji
+        )
+        .fetch_one(&state.db_pool)
+        .await
+        .map(|_| ())
+    } else {
+        sqlx::query!(
+            "DELETE FROM chat.reaction WHERE message_id = $1 AND user_id = $2 AND emoji = $3 RETURNING message_id",
+            data.message_id,
+            user.id,
+            emoji
+        )
+        .fetch_one(&state.db_pool)
+        .await
+        .map(|_| ())
+    };
+
+    match change_result {
+        Ok(_) => broadcast_reactions(socket, state, chat_id, data.message_id).await,
+        Err(sqlx::Error::RowNotFound) if add => {
+            socket
+                .emit("error", "You have already reacted with this emoji")
+                .ok();
+        }
+        Err(sqlx::Error::RowNotFound) => {
+            socket
+                .emit("error", "You haven't reacted with this emoji")
+                .ok();
+        }
+        Err(_) => {
+            socket.emit("error", "Could not change the reaction").ok();
+        }
+    }
+}
+
+pub async fn add_reaction(
+    socket: SocketRef,
+    TryData(data): TryData<ReactionInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    change_reaction(&socket, &state, data, true).await;
+}
+
+pub async fn remove_reaction(
+    socket: SocketRef,
+    TryData(data): TryData<ReactionInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    change_reaction(&socket, &state, data, false).await;
+}
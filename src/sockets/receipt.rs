@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use socketioxide::extract::{SocketRef, State, TryData};
+use sqlx::types::Uuid;
+
+use crate::{sockets::GetUser, AppState};
+
+#[derive(Deserialize)]
+pub struct MarkReadInput {
+    chat_id: Uuid,
+    /// Newest message the user has seen, everything before it counts as read too.
+    message_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct ReadReceipt {
+    chat_id: Uuid,
+    user_id: Uuid,
+    message_id: Uuid,
+    /// Creation time of the message, which lets clients compare receipts to the history.
+    read_up_to: NaiveDateTime,
+}
+
+pub async fn mark_read(
+    socket: SocketRef,
+    TryData(data): TryData<MarkReadInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    let data = match data {
+        Ok(data) => data,
+        Err(_) => {
+            socket.emit("error", "Could not parse body. Please, make sure you have all the required fields with correct names").ok();
+            return;
+        }
+    };
+    let user = match socket.get_user(&state).await {
+        Some(user) => user,
+        None => {
+            socket
+                .emit("error", "Could not authenticate the user by auth header")
+                .ok();
+            return;
+        }
+    };
+
+    match user.is_member(&state.db_pool, data.chat_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            socket.emit("error", "Could not find you in this chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Failed to check if you are in the chat")
+                .ok();
+            return;
+        }
+    }
+
+    let message_result = sqlx::query_scalar!(
+        "SELECT created_at FROM chat.message WHERE id = $1 AND chat_id = $2",
+        data.message_id,
+        data.chat_id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let read_up_to = match message_result {
+        Ok(created_at) => created_at,
+        Err(sqlx::Error::RowNotFound) => {
+            socket
+                .emit(
+                    "error",
+                    "Could not find message with such an id in this chat",
+                )
+                .ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not mark the messages as read")
+                .ok();
+            return;
+        }
+    };
+
+    // The pointer only moves forward, so that a late event from another device doesn't unread messages
+    let update_result = sqlx::query!(
+        "
+        UPDATE chat.user_chat SET last_read_message_id = $3, last_read_at = $4
+        WHERE user_id = $1 AND chat_id = $2
+            AND (last_read_at IS NULL OR (last_read_at, last_read_message_id) < ($4, $3))
+        ",
+        user.id,
+        data.chat_id,
+        data.message_id,
+        read_up_to
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match update_result {
+        Ok(result) if result.rows_affected() == 0 => {}
+        Ok(_) => {
+            socket
+                .within(data.chat_id.to_string())
+                .emit(
+                    "read-receipt",
+                    ReadReceipt {
+                        chat_id: data.chat_id,
+                        user_id: user.id,
+                        message_id: data.message_id,
+                        read_up_to,
+                    },
+                )
+                .ok();
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Could not mark the messages as read")
+                .ok();
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use socketioxide::extract::{SocketRef, State, TryData};
+use sqlx::types::Uuid;
+
+use crate::{chat::chat::is_member, sockets::SocketUser, AppState};
+
+/// Typing indicators which aren't refreshed for this long are stopped by the server.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Refreshes sent more often than this are ignored.
+const TYPING_THROTTLE: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub struct TypingInput {
+    chat_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct TypingEvent {
+    chat_id: Uuid,
+    user_id: Uuid,
+    username: String,
+}
+
+/// Chat which the user of the socket is typing in, kept in the socket extensions.
+#[derive(Clone)]
+struct Typing {
+    chat_id: Uuid,
+    /// When the indicator was last relayed, which also identifies it for the expiry.
+    refreshed_at: Instant,
+}
+
+/// Finds the user of the socket, and checks that the socket has joined the room of the chat.
+fn typing_user(socket: &SocketRef, chat_id: Uuid) -> Option<SocketUser> {
+    let user = socket.extensions.get::<SocketUser>()?;
+    let in_room = socket
+        .rooms()
+        .is_ok_and(|rooms| rooms.iter().any(|room| *room == chat_id.to_string()));
+    in_room.then_some(user)
+}
+
+fn relay_typing_stop(socket: &SocketRef, chat_id: Uuid, user: SocketUser) {
+    socket
+        .to(chat_id.to_string())
+        .emit(
+            "typing-stop",
+            TypingEvent {
+                chat_id,
+                user_id: user.id,
+                username: user.username,
+            },
+        )
+        .ok();
+}
+
+/// Stops the indicator of the socket, e.g. when it moves to another chat.
+pub fn stop_typing(socket: &SocketRef) {
+    let typing = socket.extensions.remove::<Typing>();
+    let user = socket.extensions.get::<SocketUser>();
+    if let (Some(typing), Some(user)) = (typing, user) {
+        relay_typing_stop(socket, typing.chat_id, user);
+    }
+}
+
+pub async fn typing_start(
+    socket: SocketRef,
+    TryData(data): TryData<TypingInput>,
+    State(state): State<Arc<AppState>>,
+) {
+    let data = match data {
+        Ok(data) => data,
+        Err(_) => {
+            socket.emit("error", "Could not parse body. Please, make sure you have all the required fields with correct names").ok();
+            return;
+        }
+    };
+    let user = match typing_user(&socket, data.chat_id) {
+        Some(user) => user,
+        None => {
+            socket
+                .emit("error", "Join the chat room before sending typing events")
+                .ok();
+            return;
+        }
+    };
+
+    let now = Instant::now();
+    let previous = socket.extensions.get::<Typing>();
+    if let Some(typing) = &previous {
+        if typing.chat_id == data.chat_id && now - typing.refreshed_at < TYPING_THROTTLE {
+            return;
+        }
+    }
+
+    // The socket stays in the room of a chat which the user was removed from
+    match is_member(&state.db_pool, user.id, data.chat_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            stop_typing(&socket);
+            socket.leave(data.chat_id.to_string()).ok();
+            socket.emit("error", "Could not find you in this chat").ok();
+            return;
+        }
+        Err(_) => {
+            socket
+                .emit("error", "Failed to check if you are in the chat")
+                .ok();
+            return;
+        }
+    }
+    if previous.is_some_and(|typing| typing.chat_id != data.chat_id) {
+        stop_typing(&socket);
+    }
+    socket.extensions.insert(Typing {
+        chat_id: data.chat_id,
+        refreshed_at: now,
+    });
+
+    socket
+        .to(data.chat_id.to_string())
+        .emit(
+            "typing-start",
+            TypingEvent {
+                chat_id: data.chat_id,
+                user_id: user.id,
+                username: user.username.clone(),
+            },
+        )
+        .ok();
+
+    // Stops the indicator unless it was refreshed or stopped in the meantime
+    tokio::spawn(async move {
+        tokio::time::sleep(TYPING_TIMEOUT).await;
+        let expired = socket
+            .extensions
+            .get::<Typing>()
+            .is_some_and(|typing| typing.refreshed_at == now);
+        if expired {
+            socket.extensions.remove::<Typing>();
+            relay_typing_stop(&socket, data.chat_id, user);
+        }
+    });
+}
+
+pub async fn typing_stop(socket: SocketRef, TryData(data): TryData<TypingInput>) {
+    let data = match data {
+        Ok(data) => data,
+        Err(_) => {
+            socket.emit("error", "Could not parse body. Please, make sure you have all the required fields with correct names").ok();
+            return;
+        }
+    };
+    let user = match typing_user(&socket, data.chat_id) {
+        Some(user) => user,
+        None => {
+            socket
+                .emit("error", "Join the chat room before sending typing events")
+                .ok();
+            return;
+        }
+    };
+
+    // Only a started indicator is relayed, so that stops can't flood the room either
+    let is_typing = socket
+        .extensions
+        .get::<Typing>()
+        .is_some_and(|typing| typing.chat_id == data.chat_id);
+    if is_typing {
+        socket.extensions.remove::<Typing>();
+        relay_typing_stop(&socket, data.chat_id, user);
+    }
+}
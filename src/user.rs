@@ -1,14 +1,41 @@
 use std::sync::Arc;
 
-use axum::{middleware, routing::patch, Router};
+use axum::{
+    middleware,
+    routing::{delete, get, patch, post},
+    Router,
+};
+use deletion::delete_account;
+use export::{download_export, export_data, get_export};
+use invitation::{accept_invitation, decline_invitation, get_invitations};
+use presence::{change_presence_status, get_presence};
 use user::{change_email, change_password, change_username};
 
 use crate::{middlewares::jwt_authorization, AppState};
 
+mod deletion;
+mod export;
+mod invitation;
+mod presence;
 mod user;
 
 pub fn routes(shared_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
+        .route("/me", delete(delete_account))
+        .route("/me/presence", patch(change_presence_status))
+        .route("/me/export", get(export_data))
+        .route("/me/export/:export_id", get(get_export))
+        .route("/me/export/:export_id/download", get(download_export))
+        .route("/:user_id/presence", get(get_presence))
+        .route("/invitations", get(get_invitations))
+        .route(
+            "/invitations/:invitation_id/accept",
+            post(accept_invitation),
+        )
+        .route(
+            "/invitations/:invitation_id/decline",
+            post(decline_invitation),
+        )
         .route("/change-password", patch(change_password))
         .route("/change-email", patch(change_email))
         .route("/change-username", patch(change_username))
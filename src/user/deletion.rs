@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use socketioxide::SocketIo;
+use sqlx::{types::Uuid, PgConnection};
+
+use crate::{
+    auth::{hasher::verify_password, registration::User, throttle::reset_account_throttle},
+    sockets::disconnect_user,
+    AppState,
+};
+
+use super::export::remove_export_files;
+
+/// Removes the user and everything that references them.
+/// Each chat of the user is handed over to its oldest member, or deleted if nobody else is left in it.
+/// Direct chats have no owner and are kept for the other party.
+async fn delete_user(
+    executor: &mut PgConnection,
+    user_id: Uuid,
+    delete_messages: bool,
+) -> sqlx::Result<()> {
+    // Chats of the user without other members
+    let abandoned_chats = sqlx::query_scalar!(
+        "
+        SELECT uc.chat_id FROM chat.user_chat AS uc
+        INNER JOIN chat.chat AS c
+        ON c.id = uc.chat_id
+        WHERE uc.user_id = $1 AND (uc.role = 'owner' OR c.kind = 'direct') AND NOT EXISTS (
+            SELECT 1 FROM chat.user_chat AS other WHERE other.chat_id = uc.chat_id AND other.user_id <> $1
+        )
+        ",
+        user_id
+    )
+    .fetch_all(&mut *executor)
+    .await?;
+
+    // Memberships of the user are removed first, so that the heirs don't clash with them as owners
+    let left_chats = sqlx::query_scalar!(
+        "DELETE FROM chat.user_chat WHERE user_id = $1 RETURNING chat_id AS \"chat_id!\"",
+        user_id
+    )
+    .fetch_all(&mut *executor)
+    .await?;
+
+    sqlx::query!(
+        "
+        UPDATE chat.user_chat AS uc SET role = 'owner'
+        FROM (
+            SELECT DISTINCT ON (member.chat_id) member.chat_id, member.user_id
+            FROM chat.user_chat AS member
+            INNER JOIN chat.chat AS c
+            ON c.id = member.chat_id
+            WHERE member.chat_id = ANY($1) AND c.kind = 'group' AND NOT EXISTS (
+                SELECT 1 FROM chat.user_chat AS owner
+                WHERE owner.chat_id = member.chat_id AND owner.role = 'owner'
+            )
+            ORDER BY member.chat_id, member.joined_at, member.user_id
+        ) AS heir
+        WHERE uc.chat_id = heir.chat_id AND uc.user_id = heir.user_id
+        ",
+        &left_chats
+    )
+    .execute(&mut *executor)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM chat.message WHERE chat_id = ANY($1)",
+        &abandoned_chats
+    )
+    .execute(&mut *executor)
+    .await?;
+
+    if delete_messages {
+        sqlx::query!("DELETE FROM chat.message WHERE user_id = $1", user_id)
+            .execute(&mut *executor)
+            .await?;
+    } else {
+        sqlx::query!(
+            "UPDATE chat.message SET user_id = NULL WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut *executor)
+        .await?;
+    }
+
+    sqlx::query!("DELETE FROM chat.chat WHERE id = ANY($1)", &abandoned_chats)
+        .execute(&mut *executor)
+        .await?;
+
+    sqlx::query!("DELETE FROM chat.session WHERE user_id = $1", user_id)
+        .execute(&mut *executor)
+        .await?;
+
+    sqlx::query!(
+        "DELETE FROM chat.password_reset WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *executor)
+    .await?;
+
+    sqlx::query!("DELETE FROM chat.recovery_code WHERE user_id = $1", user_id)
+        .execute(&mut *executor)
+        .await?;
+
+    sqlx::query!("DELETE FROM chat.totp WHERE user_id = $1", user_id)
+        .execute(&mut *executor)
+        .await?;
+
+    sqlx::query!("DELETE FROM chat.data_export WHERE user_id = $1", user_id)
+        .execute(&mut *executor)
+        .await?;
+
+    sqlx::query!("DELETE FROM chat.user WHERE id = $1", user_id)
+        .execute(&mut *executor)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccount {
+    password: String,
+    /// Whether to delete the messages of the user instead of keeping them without an author.
+    #[serde(default)]
+    delete_messages: bool,
+}
+
+/// Deletes the account of the user.
+/// All the sessions of the user are ended and their sockets are disconnected.
+pub async fn delete_account(
+    Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DeleteAccount>,
+) -> Response {
+    match verify_password(&payload.password, &user.password) {
+        Ok(valid) if valid => {}
+        Ok(_) => return (StatusCode::FORBIDDEN, "Password isn't correct").into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not delete your account due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    if delete_user(&mut tx, user.id, payload.delete_messages)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not delete your account due to internal reasons",
+        )
+            .into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            disconnect_user(&io, user.id);
+            remove_export_files(user.id).await;
+            reset_account_throttle(state.login_throttle.as_ref(), &user.email, user.id).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not delete your account due to internal reasons",
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,461 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        HeaderName, StatusCode,
+    },
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use futures_util::{stream, Stream, TryStreamExt};
+use serde::Serialize;
+use sqlx::{types::Uuid, Pool, Postgres};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+use crate::{auth::registration::User, chat::permission::ChatRole, AppState};
+
+/// Accounts with more messages are exported in the background.
+const INLINE_EXPORT_MAX_MESSAGES: i64 = 1000;
+/// For how long a finished export can be downloaded.
+const EXPORT_LIFETIME_DAYS: i32 = 7;
+/// Background exports pending for longer were interrupted, e.g. by a restart.
+const EXPORT_TIMEOUT_MINUTES: i32 = 60;
+/// How many lines can be fetched ahead of the ones which are already sent.
+const EXPORT_CHANNEL_SIZE: usize = 64;
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct ExportedProfile {
+    id: Uuid,
+    username: String,
+    email: String,
+    email_verified_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+struct ExportedMembership {
+    chat_id: Uuid,
+    chat_name: String,
+    joined_at: NaiveDateTime,
+    role: ChatRole,
+}
+
+#[derive(Serialize)]
+struct ExportedMessage {
+    id: Uuid,
+    chat_id: Uuid,
+    chat_name: String,
+    content: String,
+    created_at: NaiveDateTime,
+}
+
+/// Single line of the export, tagged with its `type`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ExportLine {
+    Profile(ExportedProfile),
+    Membership(ExportedMembership),
+    Message(ExportedMessage),
+}
+
+/// Serializes a single line of the export, including the line break.
+fn to_line(line: &ExportLine) -> Result<String, String> {
+    let mut content = serde_json::to_string(line).map_err(|e| e.to_string())?;
+    content.push('\n');
+    Ok(content)
+}
+
+async fn send_line(
+    lines: &mpsc::Sender<Result<String, String>>,
+    line: ExportLine,
+) -> Result<(), String> {
+    lines
+        .send(Ok(to_line(&line)?))
+        .await
+        .map_err(|_| "The export was cancelled".to_string())
+}
+
+/// Sends everything stored about the user as JSON lines, fetching the rows one at a time,
+/// so that large accounts are never held in memory at once.
+async fn stream_export(
+    executor: &Pool<Postgres>,
+    user_id: Uuid,
+    lines: &mpsc::Sender<Result<String, String>>,
+) -> Result<(), String> {
+    let profile = sqlx::query_as!(
+        ExportedProfile,
+        "SELECT id, username, email, email_verified_at FROM chat.user WHERE id = $1",
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(|e| e.to_string())?;
+    send_line(lines, ExportLine::Profile(profile)).await?;
+
+    let mut memberships = sqlx::query_as!(
+        ExportedMembership,
+        r#"
+        SELECT c.id AS chat_id, c.name AS chat_name, uc.joined_at, uc.role AS "role: ChatRole"
+        FROM chat.user_chat AS uc
+        INNER JOIN chat.chat AS c
+        ON c.id = uc.chat_id
+        WHERE uc.user_id = $1
+        ORDER BY uc.joined_at
+        "#,
+        user_id
+    )
+    .fetch(executor);
+    while let Some(membership) = memberships.try_next().await.map_err(|e| e.to_string())? {
+        send_line(lines, ExportLine::Membership(membership)).await?;
+    }
+
+    let mut messages = sqlx::query_as!(
+        ExportedMessage,
+        "
+        SELECT m.id, m.chat_id, c.name AS chat_name, m.content, m.created_at
+        FROM chat.message AS m
+        INNER JOIN chat.chat AS c
+        ON c.id = m.chat_id
+        WHERE m.user_id = $1
+        ORDER BY m.created_at
+        ",
+        user_id
+    )
+    .fetch(executor);
+    while let Some(message) = messages.try_next().await.map_err(|e| e.to_string())? {
+        send_line(lines, ExportLine::Message(message)).await?;
+    }
+
+    Ok(())
+}
+
+/// Runs `stream_export` in the background and hands out the lines as they are fetched.
+/// A failure is sent as the last item.
+fn spawn_export(executor: Pool<Postgres>, user_id: Uuid) -> mpsc::Receiver<Result<String, String>> {
+    let (sender, receiver) = mpsc::channel(EXPORT_CHANNEL_SIZE);
+    tokio::spawn(async move {
+        if let Err(e) = stream_export(&executor, user_id, &sender).await {
+            sender.send(Err(e)).await.ok();
+        }
+    });
+    receiver
+}
+
+fn export_dir() -> PathBuf {
+    std::env::var("EXPORT_DIR")
+        .unwrap_or_else(|_| "exports".to_string())
+        .into()
+}
+
+fn user_export_dir(user_id: Uuid) -> PathBuf {
+    export_dir().join(user_id.to_string())
+}
+
+fn export_path(user_id: Uuid, export_id: Uuid) -> PathBuf {
+    user_export_dir(user_id).join(format!("{export_id}.jsonl"))
+}
+
+/// Removes the files of all the exports of the user, e.g. once their account is deleted.
+pub async fn remove_export_files(user_id: Uuid) {
+    tokio::fs::remove_dir_all(user_export_dir(user_id))
+        .await
+        .ok();
+}
+
+/// Marks the interrupted exports of the user as failed, so that they aren't reported as pending forever.
+async fn fail_interrupted_exports(executor: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<()> {
+    let interrupted_exports = sqlx::query_scalar!(
+        "
+        UPDATE chat.data_export SET status = 'failed', completed_at = NOW()::timestamp
+        WHERE user_id = $1 AND status = 'pending'
+            AND created_at <= NOW()::timestamp - make_interval(mins => $2)
+        RETURNING id
+        ",
+        user_id,
+        EXPORT_TIMEOUT_MINUTES
+    )
+    .fetch_all(executor)
+    .await?;
+
+    for export_id in interrupted_exports {
+        tokio::fs::remove_file(export_path(user_id, export_id))
+            .await
+            .ok();
+    }
+    Ok(())
+}
+
+/// Writes the export into its file in `EXPORT_DIR`.
+async fn write_export_file(
+    executor: Pool<Postgres>,
+    user_id: Uuid,
+    export_id: Uuid,
+) -> Result<(), String> {
+    tokio::fs::create_dir_all(user_export_dir(user_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    let file = File::create(export_path(user_id, export_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut file = BufWriter::new(file);
+
+    let mut lines = spawn_export(executor, user_id);
+    while let Some(line) = lines.recv().await {
+        file.write_all(line?.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    file.flush().await.map_err(|e| e.to_string())
+}
+
+/// Reads the file in chunks, so that it can be sent as a response body.
+fn file_chunks(file: File) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; EXPORT_CHUNK_SIZE];
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, file)))
+    })
+}
+
+fn export_headers() -> AppendHeaders<[(HeaderName, &'static str); 2]> {
+    AppendHeaders([
+        (CONTENT_TYPE, "application/x-ndjson"),
+        (CONTENT_DISPOSITION, "attachment; filename=\"export.jsonl\""),
+    ])
+}
+
+#[derive(Serialize)]
+pub struct ExportStatus {
+    id: Uuid,
+    /// `pending`, `ready` or `failed`.
+    status: String,
+    created_at: NaiveDateTime,
+    completed_at: Option<NaiveDateTime>,
+    expires_at: Option<NaiveDateTime>,
+}
+
+/// Streams everything stored about the user as JSON lines.
+/// Large accounts are exported in the background instead, and the status of the export is returned,
+/// so that it can be polled with `get_export` and downloaded with `download_export` once ready.
+pub async fn export_data(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let count_result = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM chat.message WHERE user_id = $1"#,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match count_result {
+        Ok(messages) if messages.count <= INLINE_EXPORT_MAX_MESSAGES => {
+            // A failure after the headers are sent aborts the body, so that a partial export isn't taken for a full one
+            let lines = stream::unfold(
+                spawn_export(state.db_pool.clone(), user.id),
+                |mut lines| async move {
+                    lines
+                        .recv()
+                        .await
+                        .map(|line| (line.map_err(std::io::Error::other), lines))
+                },
+            );
+            return (StatusCode::OK, export_headers(), Body::from_stream(lines)).into_response();
+        }
+        Ok(_) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    if fail_interrupted_exports(&state.db_pool, user.id)
+        .await
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let pending_export = sqlx::query_as!(
+        ExportStatus,
+        "
+        SELECT id, status, created_at, completed_at, expires_at FROM chat.data_export
+        WHERE user_id = $1 AND status = 'pending'
+        LIMIT 1
+        ",
+        user.id
+    )
+    .fetch_optional(&state.db_pool)
+    .await;
+
+    match pending_export {
+        Ok(Some(export)) => return (StatusCode::ACCEPTED, Json(export)).into_response(),
+        Ok(None) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    // Files of the expired exports aren't needed anymore
+    let expired_exports = sqlx::query_scalar!(
+        "DELETE FROM chat.data_export WHERE user_id = $1 AND expires_at <= NOW()::timestamp RETURNING id",
+        user.id
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .unwrap_or_default();
+    for expired_id in expired_exports {
+        tokio::fs::remove_file(export_path(user.id, expired_id))
+            .await
+            .ok();
+    }
+
+    let insert_result = sqlx::query_as!(
+        ExportStatus,
+        "
+        INSERT INTO chat.data_export (user_id) VALUES ($1)
+        RETURNING id, status, created_at, completed_at, expires_at
+        ",
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let export = match insert_result {
+        Ok(export) => export,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not start the export due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    let export_id = export.id;
+    tokio::spawn(async move {
+        match write_export_file(state.db_pool.clone(), user.id, export_id).await {
+            Ok(_) => sqlx::query!(
+                "
+                UPDATE chat.data_export
+                SET status = 'ready', completed_at = NOW()::timestamp,
+                    expires_at = NOW()::timestamp + make_interval(days => $1)
+                WHERE id = $2
+                ",
+                EXPORT_LIFETIME_DAYS,
+                export_id
+            )
+            .execute(&state.db_pool)
+            .await
+            .ok(),
+            Err(_) => {
+                tokio::fs::remove_file(export_path(user.id, export_id))
+                    .await
+                    .ok();
+                sqlx::query!(
+                "UPDATE chat.data_export SET status = 'failed', completed_at = NOW()::timestamp WHERE id = $1",
+                export_id
+            )
+            .execute(&state.db_pool)
+            .await
+            .ok()
+            }
+        };
+    });
+
+    (StatusCode::ACCEPTED, Json(export)).into_response()
+}
+
+pub async fn get_export(
+    Path(export_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if fail_interrupted_exports(&state.db_pool, user.id)
+        .await
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let query_result = sqlx::query_as!(
+        ExportStatus,
+        "
+        SELECT id, status, created_at, completed_at, expires_at FROM chat.data_export
+        WHERE id = $1 AND user_id = $2
+        ",
+        export_id,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(export) => (StatusCode::OK, Json(export)).into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::NOT_FOUND,
+                "Could not find export with such an id",
+            )
+                .into_response(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    }
+}
+
+pub async fn download_export(
+    Path(export_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if fail_interrupted_exports(&state.db_pool, user.id)
+        .await
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let query_result = sqlx::query_scalar!(
+        "
+        SELECT status FROM chat.data_export
+        WHERE id = $1 AND user_id = $2 AND (expires_at IS NULL OR expires_at > NOW()::timestamp)
+        ",
+        export_id,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(status) if status == "ready" => {
+            match File::open(export_path(user.id, export_id)).await {
+                Ok(file) => (
+                    StatusCode::OK,
+                    export_headers(),
+                    Body::from_stream(file_chunks(file)),
+                )
+                    .into_response(),
+                Err(_) => (StatusCode::GONE, "The export is no longer available").into_response(),
+            }
+        }
+        Ok(status) if status == "pending" => {
+            (StatusCode::CONFLICT, "The export isn't ready yet").into_response()
+        }
+        Ok(_) => (StatusCode::GONE, "The export has failed").into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::NOT_FOUND,
+                "Could not find export with such an id",
+            )
+                .into_response(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    }
+}
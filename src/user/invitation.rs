@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use socketioxide::SocketIo;
+use sqlx::types::Uuid;
+
+use crate::{
+    auth::registration::User,
+    chat::{invitation::Invitation, invite::MemberJoined, permission::ChatRole},
+    AppState,
+};
+
+/// Lists the pending invitations of the user.
+pub async fn get_invitations(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let query_result = sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT i.id, i.chat_id, c.name AS chat_name, i.inviter_id, u.username AS "inviter_username?", i.created_at
+        FROM chat.invitation AS i
+        INNER JOIN chat.chat AS c
+        ON c.id = i.chat_id
+        LEFT JOIN chat.user AS u
+        ON u.id = i.inviter_id
+        WHERE i.invitee_id = $1 AND i.status = 'pending'
+        ORDER BY i.created_at DESC
+        "#,
+        user.id
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(invitations) => (StatusCode::OK, Json(invitations)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not find the invitations due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+/// Adds the user to the chat of the invitation.
+pub async fn accept_invitation(
+    Path(invitation_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not accept the invitation due to internal reasons",
+            )
+                .into_response();
+        }
+    };
+
+    let accept_result = sqlx::query_scalar!(
+        "
+        UPDATE chat.invitation SET status = 'accepted', responded_at = NOW()::timestamp
+        WHERE id = $1 AND invitee_id = $2 AND status = 'pending'
+        RETURNING chat_id
+        ",
+        invitation_id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let chat_id = match accept_result {
+        Ok(chat_id) => chat_id,
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    "Could not find pending invitation with such an id",
+                )
+                    .into_response()
+            }
+            _ => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not accept the invitation due to internal reasons",
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    let insert_result = sqlx::query!(
+        "INSERT INTO chat.user_chat (user_id, chat_id, role) VALUES ($1, $2, $3)",
+        user.id,
+        chat_id,
+        ChatRole::Member as ChatRole
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(err) = insert_result {
+        return match err {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                (StatusCode::CONFLICT, "You are already in this chat").into_response()
+            }
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not accept the invitation due to internal reasons",
+            )
+                .into_response(),
+        };
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            io.within(chat_id.to_string())
+                .emit(
+                    "member-joined",
+                    MemberJoined {
+                        chat_id,
+                        user_id: user.id,
+                        username: user.username,
+                    },
+                )
+                .ok();
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not accept the invitation due to internal reasons",
+        )
+            .into_response(),
+    }
+}
+
+pub async fn decline_invitation(
+    Path(invitation_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let decline_result = sqlx::query!(
+        "
+        UPDATE chat.invitation SET status = 'declined', responded_at = NOW()::timestamp
+        WHERE id = $1 AND invitee_id = $2 AND status = 'pending'
+        RETURNING id
+        ",
+        invitation_id,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match decline_result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => (
+                StatusCode::NOT_FOUND,
+                "Could not find pending invitation with such an id",
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not decline the invitation due to internal reasons",
+            )
+                .into_response(),
+        },
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use socketioxide::SocketIo;
+use sqlx::types::Uuid;
+
+use crate::{
+    auth::registration::User,
+    sockets::presence::{presence_rooms, Presence, PresenceStatus, PresenceUpdate},
+    AppState,
+};
+
+/// Shows the presence of the user themselves or of someone who shares a chat with them.
+pub async fn get_presence(
+    Path(user_id): Path<Uuid>,
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    struct StoredPresence {
+        presence_status: PresenceStatus,
+        last_seen_at: Option<NaiveDateTime>,
+    }
+    let query_result = sqlx::query_as!(
+        StoredPresence,
+        r#"
+        SELECT u.presence_status AS "presence_status: PresenceStatus", u.last_seen_at
+        FROM chat.user AS u
+        WHERE u.id = $1 AND (u.id = $2 OR EXISTS (
+            SELECT 1 FROM chat.user_chat AS mine
+            INNER JOIN chat.user_chat AS other
+            ON other.chat_id = mine.chat_id
+            WHERE mine.user_id = $2 AND other.user_id = u.id
+        ))
+        "#,
+        user_id,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    match query_result {
+        Ok(stored) => (
+            StatusCode::OK,
+            Json(PresenceUpdate {
+                user_id,
+                presence: Presence::new(state.presence.is_online(user_id), stored.presence_status),
+                last_seen_at: stored.last_seen_at,
+            }),
+        )
+            .into_response(),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                (StatusCode::NOT_FOUND, "User with such an id does not exist").into_response()
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangePresenceStatus {
+    status: PresenceStatus,
+}
+
+/// Sets the status which others see while the user is online, e.g. `away` or `do_not_disturb`.
+pub async fn change_presence_status(
+    Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ChangePresenceStatus>,
+) -> Response {
+    let update_result = sqlx::query_scalar!(
+        "UPDATE chat.user SET presence_status = $1 WHERE id = $2 RETURNING last_seen_at",
+        payload.status as PresenceStatus,
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await;
+
+    let last_seen_at = match update_result {
+        Ok(last_seen_at) => last_seen_at,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not change your status due to internal reasons",
+            )
+                .into_response()
+        }
+    };
+
+    // Offline users look the same whatever their status is
+    if state.presence.is_online(user.id) {
+        if let Ok(rooms) = presence_rooms(&state.db_pool, user.id).await {
+            io.within(rooms)
+                .emit(
+                    "presence",
+                    PresenceUpdate {
+                        user_id: user.id,
+                        presence: Presence::new(true, payload.status),
+                        last_seen_at,
+                    },
+                )
+                .ok();
+        }
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
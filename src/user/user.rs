@@ -7,9 +7,17 @@ use axum::{
     Extension, Json,
 };
 use serde::Deserialize;
+use socketioxide::SocketIo;
 
 use crate::{
-    auth::registration::{User, Validity},
+    auth::{
+        hasher::verify_password,
+        registration::{User, Validity},
+        session::invalidate_user_tokens,
+        verification::send_verification_email,
+    },
+    mailer::send_in_background,
+    sockets::disconnect_user,
     AppState,
 };
 
@@ -19,8 +27,11 @@ pub struct ChangePassword {
     old_password: String,
 }
 
+/// Changes the password of the user.
+/// All the sessions of the user are ended, so they have to log in again with the new password.
 pub async fn change_password(
     Extension(user): Extension<User>,
+    Extension(io): Extension<SocketIo>,
     State(state): State<Arc<AppState>>,
     Json(payload): Json<ChangePassword>,
 ) -> Response {
@@ -28,7 +39,7 @@ pub async fn change_password(
         return (StatusCode::FORBIDDEN, message).into_response();
     }
 
-    let is_same = bcrypt::verify(&payload.old_password, &user.password);
+    let is_same = verify_password(&payload.old_password, &user.password);
 
     match is_same {
         Ok(same) if same => {}
@@ -52,7 +63,7 @@ pub async fn change_password(
             .into_response();
     }
 
-    let pass_encrypt_res = bcrypt::hash(&payload.new_password.as_bytes(), 10);
+    let pass_encrypt_res = state.password_hasher.hash(&payload.new_password);
 
     let password = match pass_encrypt_res {
         Ok(hash) => hash,
@@ -65,14 +76,44 @@ pub async fn change_password(
         }
     };
 
+    let mut tx = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not update the password".to_string(),
+            )
+                .into_response();
+        }
+    };
+
     let result = sqlx::query("UPDATE chat.user SET password=$1 WHERE id=$2")
         .bind(password)
         .bind(user.id)
-        .execute(&state.db_pool)
+        .execute(&mut *tx)
         .await;
 
-    match result {
-        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+    if result.is_err() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not update the password".to_string(),
+        )
+            .into_response();
+    }
+
+    if invalidate_user_tokens(&mut tx, user.id).await.is_err() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not end your sessions",
+        )
+            .into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            disconnect_user(&io, user.id);
+            StatusCode::NO_CONTENT.into_response()
+        }
         Err(_) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             "Could not update the password".to_string(),
@@ -85,6 +126,9 @@ pub async fn change_password(
 pub struct ChangeEmail {
     new_email: String,
 }
+
+/// Sends the verification token to the new email of the user.
+/// The email is changed only once the token is used in `/auth/verify-email`.
 pub async fn change_email(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
@@ -102,27 +146,40 @@ pub async fn change_email(
         return (StatusCode::BAD_REQUEST, "Your new email is invalid").into_response();
     }
 
-    let update_result = sqlx::query!(
-        "UPDATE chat.user SET email = $1 WHERE id = $2",
-        payload.new_email,
-        user.id
+    let result = sqlx::query!(
+        "SELECT EXISTS (SELECT 1 FROM chat.user WHERE email = $1)",
+        payload.new_email
     )
-    .execute(&state.db_pool)
+    .fetch_one(&state.db_pool)
     .await;
 
-    match update_result {
-        Ok(_) => StatusCode::NO_CONTENT.into_response(),
-        Err(err) => match err {
-            sqlx::Error::Database(e) if e.is_unique_violation() => {
-                (StatusCode::BAD_REQUEST, "This email is already used").into_response()
+    match result {
+        Ok(val) => match val.exists {
+            Some(used) if !used => {}
+            Some(_) => {
+                return (StatusCode::BAD_REQUEST, "This email is already used").into_response();
             }
-            _ => (
+            None => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not change your email due to internal reasons",
+                )
+                    .into_response();
+            }
+        },
+        Err(_) => {
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Could not change your email due to internal reasons",
             )
-                .into_response(),
-        },
+                .into_response();
+        }
     }
+
+    send_in_background(async move {
+        send_verification_email(&state, user.id, payload.new_email, user.token_version).await
+    });
+    StatusCode::ACCEPTED.into_response()
 }
 
 #[derive(Deserialize)]